@@ -0,0 +1,350 @@
+use fitness::metric_for;
+use rand::Rng;
+use single_image::SingleImage;
+use std::collections::VecDeque;
+use wasm_bindgen::prelude::*;
+use {
+    genome_json, Color, ConfigError, EvolutionConfig, FitnessMetric, FitnessMetricKind, Gene,
+    Pixel, RandomImage,
+};
+
+/*
+Decides how the temperature changes after each annealing step. Implement this to
+plug in a custom schedule via `SimulatedAnnealing::set_schedule`.
+*/
+pub trait CoolingSchedule {
+    // `acceptance_rate` is the fraction of recent steps (see
+    // `SimulatedAnnealing::set_acceptance_window`) whose mutation was kept
+    fn next_temperature(&mut self, temperature: f64, step: u32, acceptance_rate: f64) -> f64;
+}
+
+// Drops the temperature by a fixed amount every step
+pub struct LinearCooling {
+    pub decrement: f64,
+}
+
+impl CoolingSchedule for LinearCooling {
+    fn next_temperature(&mut self, temperature: f64, _step: u32, _acceptance_rate: f64) -> f64 {
+        temperature - self.decrement
+    }
+}
+
+// Multiplies the temperature by `alpha` (just under 1) every step
+pub struct ExponentialCooling {
+    pub alpha: f64,
+}
+
+impl CoolingSchedule for ExponentialCooling {
+    fn next_temperature(&mut self, temperature: f64, _step: u32, _acceptance_rate: f64) -> f64 {
+        temperature * self.alpha
+    }
+}
+
+/*
+Steers the temperature so that roughly `target_acceptance` of mutations are kept:
+cool by `factor` while accepting too many, warm by 1/`factor` while accepting too
+few. Useful when the right temperature scale for an image isn't known up front.
+*/
+pub struct AdaptiveCooling {
+    pub target_acceptance: f64,
+    pub factor: f64,
+}
+
+impl CoolingSchedule for AdaptiveCooling {
+    fn next_temperature(&mut self, temperature: f64, _step: u32, acceptance_rate: f64) -> f64 {
+        if acceptance_rate > self.target_acceptance {
+            temperature * self.factor
+        } else {
+            temperature / self.factor
+        }
+    }
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug)]
+pub struct AnnealingStep {
+    pub step: u32,
+    pub temperature: f64,
+    pub acceptance_rate: f64,
+    pub fitness: f64,
+    pub best_fitness: f64,
+    pub accepted: bool,
+    pub reheated: bool,
+}
+
+/*
+Simulated annealing over a single image. Like `HillClimber` it makes one small
+change per step, but a worse mutation is still kept with probability exp(-Δ/T), which
+lets the search climb out of local minima while the temperature T is high.
+If the best fitness hasn't improved for `stagnation_limit` steps the temperature
+is reset to `reheat_temperature`.
+*/
+#[wasm_bindgen]
+pub struct SimulatedAnnealing {
+    search: SingleImage,
+    schedule: Box<dyn CoolingSchedule>,
+    temperature: f64,
+    min_temperature: f64,
+    reheat_temperature: f64,
+    stagnation_limit: u32,
+    steps_since_improvement: u32,
+    recent: VecDeque<bool>,
+    acceptance_window: usize,
+    step: u32,
+    reheats: u32,
+    best_genes: Vec<Gene>,
+    best_background: Color,
+    best_fitness: f64,
+}
+
+#[wasm_bindgen]
+impl SimulatedAnnealing {
+    pub fn new(
+        width: u32,
+        height: u32,
+        reference: &[u8],
+        reference_w: u32,
+        reference_h: u32,
+    ) -> Self {
+        Self::new_with_config(
+            width,
+            height,
+            reference,
+            reference_w,
+            reference_h,
+            EvolutionConfig::default(),
+        )
+        .expect("the default config is valid")
+    }
+
+    // `reference` is the reference image's RGBA bytes, e.g. `imageData.data`
+    pub fn new_with_config(
+        width: u32,
+        height: u32,
+        reference: &[u8],
+        reference_w: u32,
+        reference_h: u32,
+        config: EvolutionConfig,
+    ) -> Result<SimulatedAnnealing, JsValue> {
+        let annealing = SimulatedAnnealing::with_config(
+            width,
+            height,
+            reference.to_vec(),
+            reference_w,
+            reference_h,
+            config,
+        )?;
+        Ok(annealing)
+    }
+
+    // Chance of keeping a mutation that changed the fitness by `delta` at
+    // `temperature`: always if it improved, exp(-Δ/T) otherwise
+    pub fn acceptance_probability(delta: f64, temperature: f64) -> f64 {
+        if delta < 0.0 {
+            1.0
+        } else if temperature > 0.0 {
+            (-delta / temperature).exp()
+        } else {
+            0.0
+        }
+    }
+
+    // Re-seeds and starts over from a fresh random image at `temperature`
+    pub fn reseed(&mut self, seed: u64, temperature: f64) {
+        self.search.reseed(seed);
+        self.reset(temperature);
+    }
+
+    pub fn set_linear_cooling(&mut self, decrement: f64) {
+        self.set_schedule(Box::new(LinearCooling { decrement }));
+    }
+
+    pub fn set_exponential_cooling(&mut self, alpha: f64) {
+        self.set_schedule(Box::new(ExponentialCooling { alpha }));
+    }
+
+    pub fn set_adaptive_cooling(&mut self, target_acceptance: f64, factor: f64) {
+        self.set_schedule(Box::new(AdaptiveCooling {
+            target_acceptance,
+            factor,
+        }));
+    }
+
+    pub fn set_temperature(&mut self, temperature: f64) {
+        self.temperature = temperature;
+    }
+
+    pub fn set_min_temperature(&mut self, min_temperature: f64) {
+        self.min_temperature = min_temperature;
+    }
+
+    // Reheat to `temperature` once the best fitness has been stuck for
+    // `stagnation_limit` steps. A limit of 0 disables reheating.
+    pub fn set_reheat(&mut self, temperature: f64, stagnation_limit: u32) {
+        self.reheat_temperature = temperature;
+        self.stagnation_limit = stagnation_limit;
+    }
+
+    // Number of recent steps the acceptance rate is measured over
+    pub fn set_acceptance_window(&mut self, window: usize) {
+        self.acceptance_window = window.max(1);
+        while self.recent.len() > self.acceptance_window {
+            self.recent.pop_front();
+        }
+    }
+
+    pub fn step(&mut self) -> AnnealingStep {
+        let previous_fitness = self.search.image.fitness;
+        let config = self.search.config;
+        let delta = self.search.propose(&config) - previous_fitness;
+        let accepted = delta < 0.0
+            || (self.temperature > 0.0
+                && Self::acceptance_probability(delta, self.temperature)
+                    > self.search.rng.gen::<f64>());
+        if !accepted {
+            self.search.revert();
+        }
+
+        if self.search.image.fitness < self.best_fitness {
+            self.best_fitness = self.search.image.fitness;
+            self.best_genes = self.search.image.genes.clone();
+            self.best_background = self.search.image.background.clone();
+            self.steps_since_improvement = 0;
+        } else {
+            self.steps_since_improvement += 1;
+        }
+
+        self.recent.push_back(accepted);
+        if self.recent.len() > self.acceptance_window {
+            self.recent.pop_front();
+        }
+        let acceptance_rate = self.acceptance_rate();
+
+        self.step += 1;
+        let temperature =
+            self.schedule
+                .next_temperature(self.temperature, self.step, acceptance_rate);
+        self.temperature = temperature.max(self.min_temperature);
+
+        let reheated =
+            self.stagnation_limit > 0 && self.steps_since_improvement >= self.stagnation_limit;
+        if reheated {
+            self.temperature = self.reheat_temperature;
+            self.steps_since_improvement = 0;
+            self.reheats += 1;
+        }
+
+        AnnealingStep {
+            step: self.step,
+            temperature: self.temperature,
+            acceptance_rate,
+            fitness: self.search.image.fitness,
+            best_fitness: self.best_fitness,
+            accepted,
+            reheated,
+        }
+    }
+
+    pub fn temperature(&self) -> f64 {
+        self.temperature
+    }
+
+    pub fn acceptance_rate(&self) -> f64 {
+        if self.recent.is_empty() {
+            0.0
+        } else {
+            self.recent.iter().filter(|&&a| a).count() as f64 / self.recent.len() as f64
+        }
+    }
+
+    pub fn set_fitness_metric(&mut self, kind: FitnessMetricKind, ignore_alpha: bool) {
+        self.set_metric(metric_for(kind, ignore_alpha));
+    }
+
+    pub fn fitness(&self) -> f64 {
+        self.search.image.fitness
+    }
+
+    pub fn best_fitness(&self) -> f64 {
+        self.best_fitness
+    }
+
+    pub fn reheats(&self) -> u32 {
+        self.reheats
+    }
+
+    // Pixels of the current (not necessarily best) state
+    pub fn pixels(&self) -> *const Pixel {
+        self.search.image.pixels()
+    }
+
+    // Jumps back to the best state seen so far, e.g. at the end of a run
+    pub fn restore_best(&mut self) {
+        self.search.image.genes = self.best_genes.clone();
+        self.search.image.background = self.best_background.clone();
+        self.search.reevaluate();
+    }
+
+    pub fn best_genome_json(&self) -> String {
+        genome_json(&self.best_background, &self.best_genes)
+    }
+}
+
+impl SimulatedAnnealing {
+    pub fn with_config(
+        width: u32,
+        height: u32,
+        ref_values: Vec<u8>,
+        reference_w: u32,
+        reference_h: u32,
+        config: EvolutionConfig,
+    ) -> Result<SimulatedAnnealing, ConfigError> {
+        let initial_temperature = 1.0;
+        let mut annealing = SimulatedAnnealing {
+            search: SingleImage::new(width, height, ref_values, reference_w, reference_h, config)?,
+            schedule: Box::new(ExponentialCooling { alpha: 0.999 }),
+            temperature: initial_temperature,
+            min_temperature: 0.0,
+            reheat_temperature: initial_temperature,
+            stagnation_limit: 0,
+            steps_since_improvement: 0,
+            recent: VecDeque::new(),
+            acceptance_window: 100,
+            step: 0,
+            reheats: 0,
+            best_genes: vec![],
+            best_background: Color::white(),
+            best_fitness: 0.0,
+        };
+        annealing.reset(initial_temperature);
+        Ok(annealing)
+    }
+
+    pub fn set_schedule(&mut self, schedule: Box<dyn CoolingSchedule>) {
+        self.schedule = schedule;
+    }
+
+    pub fn image(&self) -> &RandomImage {
+        &self.search.image
+    }
+
+    // The best so far was scored by the old metric, so the run starts over
+    // from the current image
+    pub fn set_metric(&mut self, metric: Box<dyn FitnessMetric>) {
+        self.search.set_metric(metric);
+        let temperature = self.temperature;
+        self.reset(temperature);
+    }
+
+    // Starts the run over from the current image at `temperature`
+    fn reset(&mut self, temperature: f64) {
+        self.best_genes = self.search.image.genes.clone();
+        self.best_background = self.search.image.background.clone();
+        self.best_fitness = self.search.image.fitness;
+        self.temperature = temperature;
+        self.steps_since_improvement = 0;
+        self.recent.clear();
+        self.step = 0;
+        self.reheats = 0;
+    }
+}
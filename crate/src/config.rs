@@ -0,0 +1,416 @@
+use serde_json;
+use std::error::Error;
+use std::fmt;
+use wasm_bindgen::prelude::*;
+use {
+    BackgroundMode, BoundaryMode, CrossoverType, FillRule, GradientKind, InitStrategy,
+    MutationDistribution, MutationMode, ShapeKind,
+};
+
+/*
+Tunable knobs for evolution. `Population`, `RandomImage`, `Gene` and `Color` read
+their rates and jitter amounts from here instead of hard-coding them.
+From JS, build one with `EvolutionConfig.new()` and set fields, or pass a plain
+object's JSON to `EvolutionConfig.from_json(JSON.stringify({ gene_count: 100, ... }))`;
+any omitted fields fall back to the defaults.
+*/
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct EvolutionConfig {
+    // Chance that a member (or a freshly bred child) is mutated each generation
+    pub mutation_rate: f64,
+    // Fraction of the population replaced by offspring each generation
+    pub cull_fraction: f64,
+    // Number of genes (shapes) in a new image
+    pub gene_count: u32,
+    // Size of the window a vertex moves in, as a fraction of the image
+    // width/height (see `mutation_distribution`)
+    pub vertex_jitter: f64,
+    // Typical change of a color channel, in 0-255 units
+    pub color_jitter: f64,
+    pub tournament_size: u32,
+    pub crossover_rate: f64,
+    pub crossover_type: CrossoverType,
+    // Chances of each structural mutation per mutation event: insert a random
+    // gene, delete a gene, insert a jittered copy of a gene, swap two genes in
+    // the draw order, move a gene to another place in the draw order
+    pub add_gene_rate: f64,
+    pub remove_gene_rate: f64,
+    pub duplicate_gene_rate: f64,
+    pub swap_gene_rate: f64,
+    pub move_gene_rate: f64,
+    // Bounds on the gene count that add/remove/duplicate and crossover respect
+    pub min_genes: u32,
+    pub max_genes: u32,
+    pub mutation_mode: MutationMode,
+    // Relative chances of each component type in `MutationMode::Component`
+    pub vertex_weight: f64,
+    pub channel_weight: f64,
+    pub alpha_weight: f64,
+    pub translate_weight: f64,
+    // 1/5th success rule for `HillClimber`: every `adaptation_interval` steps,
+    // grow the jitter if more than 1/5 of steps succeeded, shrink it if fewer
+    pub one_fifth_rule: bool,
+    pub adaptation_interval: u32,
+    // Multiplier used to shrink the step size; its inverse grows it
+    pub adaptation_factor: f64,
+    // Per-gene self-adaptive step sizes for the population path
+    pub self_adaptive: bool,
+    // Bounds on both kinds of step size multiplier
+    pub min_step_scale: f64,
+    pub max_step_scale: f64,
+    // Shape of mutation steps, and how each kind of parameter handles steps
+    // that land outside its range
+    pub mutation_distribution: MutationDistribution,
+    pub vertex_boundary: BoundaryMode,
+    pub color_boundary: BoundaryMode,
+    pub alpha_boundary: BoundaryMode,
+    // Coarse-to-fine scoring (`Population::use_pyramid`): move to the next finer
+    // level after `pyramid_patience` generations in which the best fitness
+    // improved by less than `pyramid_min_improvement` (relative)
+    pub pyramid_patience: u32,
+    pub pyramid_min_improvement: f64,
+    // How strongly new genes, and genes picked for mutation, are drawn toward
+    // where the best image is most wrong: 0 is uniform, 1 is in proportion to
+    // the error
+    pub error_bias: f64,
+    // How new images pick their genes, and whether an evolving background
+    // starts on the reference's mean color instead of white
+    pub init_strategy: InitStrategy,
+    pub init_mean_background: bool,
+    // Chance per mutation event (per step for `HillClimber` and
+    // `SimulatedAnnealing`, which then skip the random mutation) of giving one
+    // gene its optimal color, see `RandomImage::optimize_gene_color`
+    pub color_solve_rate: f64,
+    // Where the background color comes from (see `BackgroundMode`), the
+    // 0xRRGGBB color `BackgroundMode::Fixed` uses, and the chance per mutation
+    // event of jittering the background when it evolves
+    pub background_mode: BackgroundMode,
+    pub background_color: u32,
+    pub background_mutation_rate: f64,
+    // What new genes draw: one kind of shape, or a mix
+    pub shape_kind: ShapeKind,
+    // For polygon genes: how self-intersecting outlines are filled, the most
+    // vertices one can have (the least is 3), and the chances per mutation
+    // event of inserting or deleting a vertex of a random polygon gene
+    pub polygon_fill_rule: FillRule,
+    pub max_polygon_vertices: u32,
+    pub add_vertex_rate: f64,
+    pub remove_vertex_rate: f64,
+    // Whether stroke genes can narrow toward their end; if not they keep one
+    // width along the whole curve
+    pub stroke_taper: bool,
+    // Whether new genes are filled flat or with a gradient (see `GradientKind`)
+    pub gradient_kind: GradientKind,
+}
+
+impl Default for EvolutionConfig {
+    fn default() -> Self {
+        Self {
+            mutation_rate: 0.5,
+            cull_fraction: 0.25,
+            gene_count: 50,
+            vertex_jitter: 0.05,
+            color_jitter: 5.0,
+            tournament_size: 3,
+            crossover_rate: 0.7,
+            crossover_type: CrossoverType::Uniform,
+            add_gene_rate: 0.02,
+            remove_gene_rate: 0.02,
+            duplicate_gene_rate: 0.02,
+            swap_gene_rate: 0.01,
+            move_gene_rate: 0.01,
+            min_genes: 1,
+            max_genes: 200,
+            mutation_mode: MutationMode::All,
+            vertex_weight: 1.0,
+            channel_weight: 1.0,
+            alpha_weight: 1.0,
+            translate_weight: 1.0,
+            one_fifth_rule: true,
+            adaptation_interval: 20,
+            adaptation_factor: 0.85,
+            self_adaptive: true,
+            min_step_scale: 0.01,
+            max_step_scale: 10.0,
+            mutation_distribution: MutationDistribution::Uniform,
+            vertex_boundary: BoundaryMode::Clamp,
+            color_boundary: BoundaryMode::Clamp,
+            alpha_boundary: BoundaryMode::Clamp,
+            pyramid_patience: 10,
+            pyramid_min_improvement: 0.001,
+            error_bias: 0.0,
+            init_strategy: InitStrategy::Random,
+            init_mean_background: false,
+            color_solve_rate: 0.0,
+            background_mode: BackgroundMode::Evolve,
+            background_color: 0xffffff,
+            background_mutation_rate: 0.05,
+            shape_kind: ShapeKind::Triangle,
+            polygon_fill_rule: FillRule::EvenOdd,
+            max_polygon_vertices: 8,
+            add_vertex_rate: 0.02,
+            remove_vertex_rate: 0.02,
+            stroke_taper: false,
+            gradient_kind: GradientKind::Flat,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl EvolutionConfig {
+    pub fn new() -> EvolutionConfig {
+        EvolutionConfig::default()
+    }
+
+    pub fn from_json(json: &str) -> Result<EvolutionConfig, JsValue> {
+        Ok(EvolutionConfig::parse(json)?)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.validate().is_ok()
+    }
+}
+
+impl EvolutionConfig {
+    // Parses a (possibly partial) config from JSON and validates it
+    pub fn parse(json: &str) -> Result<EvolutionConfig, ConfigError> {
+        let config: EvolutionConfig =
+            serde_json::from_str(json).map_err(|e| ConfigError::Json(e.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        // Written so that NaN is never in range
+        fn is_fraction(v: f64) -> bool {
+            (0.0..=1.0).contains(&v)
+        }
+
+        if !is_fraction(self.mutation_rate) {
+            return Err(ConfigError::MutationRate(self.mutation_rate));
+        }
+        // Culling everything would leave nobody to breed from
+        if !(self.cull_fraction >= 0.0 && self.cull_fraction < 1.0) {
+            return Err(ConfigError::CullFraction(self.cull_fraction));
+        }
+        if self.gene_count == 0 {
+            return Err(ConfigError::ZeroGenes);
+        }
+        if !is_fraction(self.vertex_jitter) {
+            return Err(ConfigError::VertexJitter(self.vertex_jitter));
+        }
+        if !(self.color_jitter >= 0.0 && self.color_jitter <= 255.0) {
+            return Err(ConfigError::ColorJitter(self.color_jitter));
+        }
+        if self.tournament_size == 0 {
+            return Err(ConfigError::ZeroTournamentSize);
+        }
+        if !is_fraction(self.crossover_rate) {
+            return Err(ConfigError::CrossoverRate(self.crossover_rate));
+        }
+        let structural_rates = [
+            ("add_gene_rate", self.add_gene_rate),
+            ("remove_gene_rate", self.remove_gene_rate),
+            ("duplicate_gene_rate", self.duplicate_gene_rate),
+            ("swap_gene_rate", self.swap_gene_rate),
+            ("move_gene_rate", self.move_gene_rate),
+            ("add_vertex_rate", self.add_vertex_rate),
+            ("remove_vertex_rate", self.remove_vertex_rate),
+        ];
+        for &(name, rate) in structural_rates.iter() {
+            if !is_fraction(rate) {
+                return Err(ConfigError::StructuralRate(name, rate));
+            }
+        }
+        if self.min_genes == 0
+            || self.min_genes > self.gene_count
+            || self.gene_count > self.max_genes
+        {
+            return Err(ConfigError::GeneBounds {
+                min: self.min_genes,
+                max: self.max_genes,
+                gene_count: self.gene_count,
+            });
+        }
+        let weights = [
+            self.vertex_weight,
+            self.channel_weight,
+            self.alpha_weight,
+            self.translate_weight,
+        ];
+        let total: f64 = weights.iter().sum();
+        if weights.iter().any(|&w| w.is_nan() || w < 0.0) || total.is_nan() || total <= 0.0 {
+            return Err(ConfigError::ComponentWeights);
+        }
+        if self.adaptation_interval == 0 {
+            return Err(ConfigError::ZeroAdaptationInterval);
+        }
+        if !(self.adaptation_factor > 0.0 && self.adaptation_factor < 1.0) {
+            return Err(ConfigError::AdaptationFactor(self.adaptation_factor));
+        }
+        if !(self.min_step_scale > 0.0 && self.min_step_scale <= 1.0 && self.max_step_scale >= 1.0)
+        {
+            return Err(ConfigError::StepScaleBounds {
+                min: self.min_step_scale,
+                max: self.max_step_scale,
+            });
+        }
+        if self.pyramid_patience == 0 {
+            return Err(ConfigError::ZeroPyramidPatience);
+        }
+        if !(self.pyramid_min_improvement >= 0.0 && self.pyramid_min_improvement < 1.0) {
+            return Err(ConfigError::PyramidMinImprovement(
+                self.pyramid_min_improvement,
+            ));
+        }
+        if !is_fraction(self.error_bias) {
+            return Err(ConfigError::ErrorBias(self.error_bias));
+        }
+        if !is_fraction(self.color_solve_rate) {
+            return Err(ConfigError::ColorSolveRate(self.color_solve_rate));
+        }
+        if self.background_color > 0xffffff {
+            return Err(ConfigError::BackgroundColor(self.background_color));
+        }
+        if !is_fraction(self.background_mutation_rate) {
+            return Err(ConfigError::BackgroundMutationRate(
+                self.background_mutation_rate,
+            ));
+        }
+        if self.max_polygon_vertices < 3 {
+            return Err(ConfigError::MaxPolygonVertices(self.max_polygon_vertices));
+        }
+        Ok(())
+    }
+
+    // A copy of this config with the vertex and color jitter scaled by `scale`
+    pub fn with_step_scale(&self, scale: f64) -> EvolutionConfig {
+        EvolutionConfig {
+            vertex_jitter: (self.vertex_jitter * scale).min(1.0),
+            color_jitter: (self.color_jitter * scale).min(255.0),
+            ..*self
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConfigError {
+    Json(String),
+    MutationRate(f64),
+    CullFraction(f64),
+    ZeroGenes,
+    VertexJitter(f64),
+    ColorJitter(f64),
+    ZeroTournamentSize,
+    CrossoverRate(f64),
+    StructuralRate(&'static str, f64),
+    GeneBounds { min: u32, max: u32, gene_count: u32 },
+    ComponentWeights,
+    ZeroAdaptationInterval,
+    AdaptationFactor(f64),
+    StepScaleBounds { min: f64, max: f64 },
+    ZeroPyramidPatience,
+    PyramidMinImprovement(f64),
+    ErrorBias(f64),
+    ColorSolveRate(f64),
+    BackgroundColor(u32),
+    BackgroundMutationRate(f64),
+    MaxPolygonVertices(u32),
+    WeightMapSize { expected: usize, got: usize },
+    WeightMapValues,
+    ChannelWeights([f64; 4]),
+    ZeroSsimWindow,
+    ZeroSsimScales,
+    SsimConstants(f64, f64),
+    MseWeight(f64),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Json(e) => write!(f, "invalid config JSON: {}", e),
+            ConfigError::MutationRate(v) => write!(f, "mutation_rate must be in [0, 1], got {}", v),
+            ConfigError::CullFraction(v) => write!(f, "cull_fraction must be in [0, 1), got {}", v),
+            ConfigError::ZeroGenes => write!(f, "gene_count must be at least 1"),
+            ConfigError::VertexJitter(v) => write!(f, "vertex_jitter must be in [0, 1], got {}", v),
+            ConfigError::ColorJitter(v) => write!(f, "color_jitter must be in [0, 255], got {}", v),
+            ConfigError::ZeroTournamentSize => write!(f, "tournament_size must be at least 1"),
+            ConfigError::CrossoverRate(v) => {
+                write!(f, "crossover_rate must be in [0, 1], got {}", v)
+            }
+            ConfigError::StructuralRate(name, v) => {
+                write!(f, "{} must be in [0, 1], got {}", name, v)
+            }
+            ConfigError::GeneBounds {
+                min,
+                max,
+                gene_count,
+            } => write!(
+                f,
+                "need 1 <= min_genes <= gene_count <= max_genes, got {} <= {} <= {}",
+                min, gene_count, max
+            ),
+            ConfigError::ComponentWeights => {
+                write!(f, "component weights must be non-negative and not all zero")
+            }
+            ConfigError::ZeroAdaptationInterval => {
+                write!(f, "adaptation_interval must be at least 1")
+            }
+            ConfigError::AdaptationFactor(v) => {
+                write!(f, "adaptation_factor must be in (0, 1), got {}", v)
+            }
+            ConfigError::StepScaleBounds { min, max } => write!(
+                f,
+                "need 0 < min_step_scale <= 1 <= max_step_scale, got {} and {}",
+                min, max
+            ),
+            ConfigError::ZeroPyramidPatience => write!(f, "pyramid_patience must be at least 1"),
+            ConfigError::PyramidMinImprovement(v) => {
+                write!(f, "pyramid_min_improvement must be in [0, 1), got {}", v)
+            }
+            ConfigError::ErrorBias(v) => write!(f, "error_bias must be in [0, 1], got {}", v),
+            ConfigError::ColorSolveRate(v) => {
+                write!(f, "color_solve_rate must be in [0, 1], got {}", v)
+            }
+            ConfigError::BackgroundColor(v) => {
+                write!(f, "background_color must be 0xRRGGBB, got {:#x}", v)
+            }
+            ConfigError::BackgroundMutationRate(v) => {
+                write!(f, "background_mutation_rate must be in [0, 1], got {}", v)
+            }
+            ConfigError::MaxPolygonVertices(v) => {
+                write!(f, "max_polygon_vertices must be at least 3, got {}", v)
+            }
+            ConfigError::WeightMapSize { expected, got } => write!(
+                f,
+                "weight map needs one weight per reference pixel ({}), got {}",
+                expected, got
+            ),
+            ConfigError::WeightMapValues => write!(
+                f,
+                "weight map values must be finite, non-negative and not all zero"
+            ),
+            ConfigError::ChannelWeights(w) => write!(
+                f,
+                "channel weights must be finite, non-negative and not all zero, got {:?}",
+                w
+            ),
+            ConfigError::ZeroSsimWindow => write!(f, "SSIM window must be at least 1"),
+            ConfigError::ZeroSsimScales => write!(f, "SSIM scales must be at least 1"),
+            ConfigError::SsimConstants(k1, k2) => {
+                write!(f, "SSIM k1 and k2 must be finite, got {} and {}", k1, k2)
+            }
+            ConfigError::MseWeight(v) => write!(f, "mse_weight must be in [0, 1], got {}", v),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl From<ConfigError> for JsValue {
+    fn from(err: ConfigError) -> JsValue {
+        JsValue::from_str(&err.to_string())
+    }
+}
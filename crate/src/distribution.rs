@@ -0,0 +1,91 @@
+use clamp;
+use rand::distributions::{Cauchy, Distribution, StandardNormal};
+use rand::Rng;
+use rand_pcg::Pcg32;
+use wasm_bindgen::prelude::*;
+
+/*
+Shape of the random step a mutation takes. `Uniform` steps anywhere within
+±jitter; `Gaussian` mostly takes small steps (σ = jitter / 2, so ~95% land within
+±jitter); `Cauchy` is like Gaussian but heavy-tailed, so it occasionally makes
+a big jump, which helps escape local minima.
+*/
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub enum MutationDistribution {
+    Uniform,
+    Gaussian,
+    Cauchy,
+}
+
+/*
+What happens when a step lands outside a parameter's range.
+`Clamp` pins it to the nearest edge, which piles values up on the edges.
+`Reflect` bounces it back off the edge and `Wrap` brings it in from the opposite
+edge; both keep the distribution of values unbiased near the edges. `Wrap` treats
+the range as a circle on which `min` and `max` are the same point, so values
+that land exactly on either edge stay where they are.
+*/
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub enum BoundaryMode {
+    Clamp,
+    Reflect,
+    Wrap,
+}
+
+// A random offset of typical size `jitter`. Never panics, even for a zero jitter.
+pub fn random_step(jitter: f64, distribution: MutationDistribution, rng: &mut Pcg32) -> f64 {
+    if jitter.is_nan() || jitter <= 0.0 {
+        return 0.0;
+    }
+    match distribution {
+        MutationDistribution::Uniform => rng.gen_range(-jitter, jitter),
+        MutationDistribution::Gaussian => {
+            let n: f64 = rng.sample(StandardNormal);
+            n * jitter / 2.0
+        }
+        MutationDistribution::Cauchy => Cauchy::new(0.0, jitter / 2.0).sample(rng),
+    }
+}
+
+// Brings `v` back inside [min, max]
+pub fn apply_boundary(v: f64, min: f64, max: f64, boundary: BoundaryMode) -> f64 {
+    let range = max - min;
+    if !v.is_finite() || range <= 0.0 {
+        return clamp(v, min, max);
+    }
+    match boundary {
+        BoundaryMode::Clamp => clamp(v, min, max),
+        BoundaryMode::Reflect => {
+            // Reflecting back and forth between the edges repeats every 2 * range
+            let period = 2.0 * range;
+            let t = (v - min) % period;
+            let t = if t < 0.0 { t + period } else { t };
+            min + if t > range { period - t } else { t }
+        }
+        BoundaryMode::Wrap if (min..=max).contains(&v) => v,
+        BoundaryMode::Wrap => {
+            let t = (v - min) % range;
+            min + if t < 0.0 { t + range } else { t }
+        }
+    }
+}
+
+// Moves `v` by a random step and keeps the result inside [min, max]
+pub fn perturb(
+    v: f64,
+    jitter: f64,
+    min: f64,
+    max: f64,
+    distribution: MutationDistribution,
+    boundary: BoundaryMode,
+    rng: &mut Pcg32,
+) -> f64 {
+    apply_boundary(
+        v + random_step(jitter, distribution, rng),
+        min,
+        max,
+        boundary,
+    )
+}
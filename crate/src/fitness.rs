@@ -0,0 +1,616 @@
+use guide::ErrorGuide;
+use lab::{DeltaE, DeltaEFormula};
+use pyramid::{gaussian_pyramid, resample, Level};
+use shape::BBox;
+use ssim::{MsSsim, Ssim};
+use wasm_bindgen::prelude::*;
+use {clamp, ConfigError, Pixel, RandomImage};
+
+/*
+How far a (shrunk) image is from the reference. Lower is better, and a perfect
+match should score 0 so that fitnesses stay non-negative.
+`reference` holds RGBA bytes, 4 per pixel, in the same order as `pixels`.
+*/
+pub trait FitnessMetric {
+    fn error(&self, pixels: &[Pixel], reference: &[u8], width: u32, height: u32) -> f64;
+
+    // Called with the reference when the metric is installed (and with each
+    // coarser copy of it, when multi-resolution scoring is on), so it can cache
+    // anything derived from them instead of recomputing it every evaluation.
+    // Each call replaces what the last one prepared.
+    fn prepare(&mut self, _references: &[&[u8]]) {}
+
+    // The error at each pixel, for metrics that are a mean over pixels (so the
+    // mean of these is `error`). None for metrics that aren't, like SSIM.
+    fn pixel_errors(
+        &self,
+        _pixels: &[Pixel],
+        _reference: &[u8],
+        _width: u32,
+        _height: u32,
+    ) -> Option<Vec<f64>> {
+        None
+    }
+
+    // `error`, with each pixel's contribution scaled by `weights` (one per
+    // pixel). Metrics without `pixel_errors` should override this.
+    fn weighted_error(
+        &self,
+        pixels: &[Pixel],
+        reference: &[u8],
+        weights: &[f64],
+        width: u32,
+        height: u32,
+    ) -> f64 {
+        match self.pixel_errors(pixels, reference, width, height) {
+            Some(errors) => weighted_mean(&errors, weights),
+            None => self.error(pixels, reference, width, height),
+        }
+    }
+}
+
+// Mean squared error per channel value
+pub struct Mse {
+    pub ignore_alpha: bool,
+}
+
+impl FitnessMetric for Mse {
+    fn error(&self, pixels: &[Pixel], reference: &[u8], _width: u32, _height: u32) -> f64 {
+        mean(&channel_errors(
+            pixels,
+            reference,
+            channel_weights(self.ignore_alpha),
+            |d| d * d,
+        ))
+    }
+
+    fn pixel_errors(
+        &self,
+        pixels: &[Pixel],
+        reference: &[u8],
+        _width: u32,
+        _height: u32,
+    ) -> Option<Vec<f64>> {
+        Some(channel_errors(
+            pixels,
+            reference,
+            channel_weights(self.ignore_alpha),
+            |d| d * d,
+        ))
+    }
+}
+
+// Mean absolute error per channel value; punishes a few big misses less than MSE
+pub struct Mae {
+    pub ignore_alpha: bool,
+}
+
+impl FitnessMetric for Mae {
+    fn error(&self, pixels: &[Pixel], reference: &[u8], _width: u32, _height: u32) -> f64 {
+        mean(&channel_errors(
+            pixels,
+            reference,
+            channel_weights(self.ignore_alpha),
+            f64::abs,
+        ))
+    }
+
+    fn pixel_errors(
+        &self,
+        pixels: &[Pixel],
+        reference: &[u8],
+        _width: u32,
+        _height: u32,
+    ) -> Option<Vec<f64>> {
+        Some(channel_errors(
+            pixels,
+            reference,
+            channel_weights(self.ignore_alpha),
+            f64::abs,
+        ))
+    }
+}
+
+/*
+Peak signal-to-noise ratio. PSNR grows as images get closer (and is infinite for
+identical ones), so to fit the lower-is-better convention the error reported is
+how far the PSNR is below `MAX_PSNR`.
+*/
+pub struct Psnr {
+    pub ignore_alpha: bool,
+}
+
+pub const MAX_PSNR: f64 = 100.0;
+
+impl Psnr {
+    fn error_for_mse(mse: f64) -> f64 {
+        let psnr = if mse > 0.0 {
+            10.0 * (255.0 * 255.0 / mse).log10()
+        } else {
+            MAX_PSNR
+        };
+        (MAX_PSNR - psnr).max(0.0)
+    }
+
+    fn squared_errors(&self, pixels: &[Pixel], reference: &[u8]) -> Vec<f64> {
+        channel_errors(pixels, reference, channel_weights(self.ignore_alpha), |d| {
+            d * d
+        })
+    }
+}
+
+impl FitnessMetric for Psnr {
+    fn error(&self, pixels: &[Pixel], reference: &[u8], _width: u32, _height: u32) -> f64 {
+        Psnr::error_for_mse(mean(&self.squared_errors(pixels, reference)))
+    }
+
+    // PSNR of the weighted MSE
+    fn weighted_error(
+        &self,
+        pixels: &[Pixel],
+        reference: &[u8],
+        weights: &[f64],
+        _width: u32,
+        _height: u32,
+    ) -> f64 {
+        Psnr::error_for_mse(weighted_mean(
+            &self.squared_errors(pixels, reference),
+            weights,
+        ))
+    }
+}
+
+// MSE with a weight per channel, e.g. to care more about green, or (a = 0) to
+// ignore alpha
+pub struct WeightedMse {
+    weights: [f64; 4],
+}
+
+impl WeightedMse {
+    // Weights must be finite, non-negative and not all zero
+    pub fn new(weights: [f64; 4]) -> Result<WeightedMse, ConfigError> {
+        let total: f64 = weights.iter().sum();
+        if weights.iter().any(|&w| !w.is_finite() || w < 0.0) || total <= 0.0 {
+            return Err(ConfigError::ChannelWeights(weights));
+        }
+        Ok(WeightedMse { weights })
+    }
+}
+
+impl FitnessMetric for WeightedMse {
+    fn error(&self, pixels: &[Pixel], reference: &[u8], _width: u32, _height: u32) -> f64 {
+        mean(&channel_errors(pixels, reference, self.weights, |d| d * d))
+    }
+
+    fn pixel_errors(
+        &self,
+        pixels: &[Pixel],
+        reference: &[u8],
+        _width: u32,
+        _height: u32,
+    ) -> Option<Vec<f64>> {
+        Some(channel_errors(pixels, reference, self.weights, |d| d * d))
+    }
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FitnessMetricKind {
+    Mse,
+    Mae,
+    Psnr,
+    // SSIM variants use the default window and constants and ignore alpha
+    Ssim,
+    MsSsim,
+    // Perceptual color differences in CIELAB; these ignore alpha too
+    DeltaE76,
+    DeltaE2000,
+}
+
+pub fn metric_for(kind: FitnessMetricKind, ignore_alpha: bool) -> Box<dyn FitnessMetric> {
+    match kind {
+        FitnessMetricKind::Mse => Box::new(Mse { ignore_alpha }),
+        FitnessMetricKind::Mae => Box::new(Mae { ignore_alpha }),
+        FitnessMetricKind::Psnr => Box::new(Psnr { ignore_alpha }),
+        FitnessMetricKind::Ssim => Box::new(Ssim::default()),
+        FitnessMetricKind::MsSsim => Box::new(MsSsim::default()),
+        FitnessMetricKind::DeltaE76 => Box::new(DeltaE::new(DeltaEFormula::Cie76)),
+        FitnessMetricKind::DeltaE2000 => Box::new(DeltaE::new(DeltaEFormula::Ciede2000)),
+    }
+}
+
+fn channel_weights(ignore_alpha: bool) -> [f64; 4] {
+    [1.0, 1.0, 1.0, if ignore_alpha { 0.0 } else { 1.0 }]
+}
+
+// For each pixel, the weighted mean of `f(pixel channel - reference channel)`
+// over its channels
+fn channel_errors<F>(pixels: &[Pixel], reference: &[u8], weights: [f64; 4], f: F) -> Vec<f64>
+where
+    F: Fn(f64) -> f64,
+{
+    if pixels.len() * 4 != reference.len() {
+        panic!(
+            "Got bad sizes for compare: {} pixels vs {} reference values",
+            pixels.len(),
+            reference.len()
+        );
+    }
+
+    let total_weight: f64 = weights.iter().sum();
+    if total_weight.is_nan() || total_weight <= 0.0 {
+        return vec![0.0; pixels.len()];
+    }
+    pixels
+        .iter()
+        .zip(reference.chunks(4))
+        .map(|(pixel, values)| {
+            let channels = [pixel.r, pixel.g, pixel.b, pixel.a];
+            let mut err = 0.0;
+            for c in 0..4 {
+                if weights[c] != 0.0 {
+                    err += weights[c] * f(channels[c] as f64 - values[c] as f64);
+                }
+            }
+            err / total_weight
+        })
+        .collect()
+}
+
+pub fn mean(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        0.0
+    } else {
+        values.iter().sum::<f64>() / values.len() as f64
+    }
+}
+
+pub fn weighted_mean(values: &[f64], weights: &[f64]) -> f64 {
+    let total_weight: f64 = weights.iter().sum();
+    if total_weight > 0.0 {
+        values.iter().zip(weights).map(|(v, w)| v * w).sum::<f64>() / total_weight
+    } else {
+        mean(values)
+    }
+}
+
+// Colors errors black (0) through red and yellow to white (the largest), as
+// RGBA bytes
+pub fn heatmap(errors: &[f64]) -> Vec<u8> {
+    let max = errors.iter().cloned().fold(0.0, f64::max);
+    let mut rgba = Vec::with_capacity(errors.len() * 4);
+    for &e in errors {
+        let t = if max > 0.0 { e / max } else { 0.0 };
+        let ramp = |offset: f64| (255.0 * clamp(3.0 * t - offset, 0.0, 1.0)).round() as u8;
+        rgba.extend_from_slice(&[ramp(0.0), ramp(1.0), ramp(2.0), 255]);
+    }
+    rgba
+}
+
+/*
+A per-pixel weight map that emphasizes edges: the Sobel gradient magnitude of
+the reference's luminance, scaled so the strongest edge gets 1. Flat areas get
+`floor` rather than 0 so they still count for something.
+*/
+pub fn edge_weights(values: &[u8], width: u32, height: u32, floor: f64) -> Vec<f64> {
+    let (w, h) = (width as i64, height as i64);
+    let luma: Vec<f64> = values
+        .chunks(4)
+        .map(|v| 0.299 * v[0] as f64 + 0.587 * v[1] as f64 + 0.114 * v[2] as f64)
+        .collect();
+    // Edge pixels reuse their nearest neighbor inside the image
+    let at = |x: i64, y: i64| luma[(y.max(0).min(h - 1) * w + x.max(0).min(w - 1)) as usize];
+
+    let mut magnitudes = Vec::with_capacity(luma.len());
+    for y in 0..h {
+        for x in 0..w {
+            let gx = at(x + 1, y - 1) + 2.0 * at(x + 1, y) + at(x + 1, y + 1)
+                - at(x - 1, y - 1)
+                - 2.0 * at(x - 1, y)
+                - at(x - 1, y + 1);
+            let gy = at(x - 1, y + 1) + 2.0 * at(x, y + 1) + at(x + 1, y + 1)
+                - at(x - 1, y - 1)
+                - 2.0 * at(x, y - 1)
+                - at(x + 1, y - 1);
+            magnitudes.push((gx * gx + gy * gy).sqrt());
+        }
+    }
+
+    let max = magnitudes.iter().cloned().fold(0.0, f64::max);
+    magnitudes
+        .iter()
+        .map(|&m| {
+            let edge = if max > 0.0 { m / max } else { 0.0 };
+            floor + (1.0 - floor) * edge
+        })
+        .collect()
+}
+
+/*
+The image being approximated, plus how to score an attempt against it.
+Shared by `Population`, `HillClimber` and `SimulatedAnnealing`.
+*/
+pub struct Reference {
+    pub values: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    metric: Box<dyn FitnessMetric>,
+    // Optional importance of each pixel, see `set_weights`
+    weights: Option<Vec<f64>>,
+    // Coarser, blurred copies of the reference, finest first; see `set_pyramid`
+    pyramid: Vec<Level>,
+    // The level images are scored at: 0 is the full reference, i is pyramid[i - 1]
+    level: usize,
+    // Score at every level and average, instead of at just `level`
+    combine_levels: bool,
+}
+
+impl Reference {
+    pub fn new(values: Vec<u8>, width: u32, height: u32) -> Reference {
+        Reference {
+            values,
+            width,
+            height,
+            metric: Box::new(Mse {
+                ignore_alpha: false,
+            }),
+            weights: None,
+            pyramid: vec![],
+            level: 0,
+            combine_levels: false,
+        }
+    }
+
+    pub fn set_metric(&mut self, metric: Box<dyn FitnessMetric>) {
+        self.metric = metric;
+        self.prepare_metric();
+    }
+
+    // Lets the metric cache what it needs about the reference and each
+    // pyramid level
+    fn prepare_metric(&mut self) {
+        let mut references: Vec<&[u8]> = vec![&self.values];
+        references.extend(self.pyramid.iter().map(|level| &level.values[..]));
+        self.metric.prepare(&references);
+    }
+
+    /*
+    Scales each pixel's share of the error by its weight, so that e.g. a face
+    can matter more than the background. One non-negative weight per reference
+    pixel, in the same order; only their relative sizes matter.
+    None goes back to weighting every pixel equally.
+    */
+    pub fn set_weights(&mut self, weights: Option<Vec<f64>>) -> Result<(), ConfigError> {
+        if let Some(ref weights) = weights {
+            let expected = (self.width * self.height) as usize;
+            if weights.len() != expected {
+                return Err(ConfigError::WeightMapSize {
+                    expected,
+                    got: weights.len(),
+                });
+            }
+            let total: f64 = weights.iter().sum();
+            if weights.iter().any(|&w| !w.is_finite() || w < 0.0) || total <= 0.0 {
+                return Err(ConfigError::WeightMapValues);
+            }
+        }
+        self.weights = weights;
+        self.resample_weights();
+        Ok(())
+    }
+
+    /*
+    Multi-resolution scoring against a Gaussian pyramid of `levels` levels (the
+    full reference plus up to `levels - 1` coarser ones; fewer if the reference
+    is too small). Scoring starts at the coarsest level, and `promote` moves
+    it to finer ones. With `combine`, every level is scored and the results
+    averaged instead. `levels` <= 1 turns this off.
+    The canvas size is needed so each level is one `shrink` can produce.
+    */
+    pub fn set_pyramid(
+        &mut self,
+        levels: u32,
+        canvas_width: u32,
+        canvas_height: u32,
+        combine: bool,
+    ) {
+        self.pyramid = gaussian_pyramid(
+            &self.values,
+            self.width,
+            self.height,
+            canvas_width,
+            canvas_height,
+            levels,
+        );
+        self.resample_weights();
+        self.prepare_metric();
+        self.combine_levels = combine;
+        self.level = if combine { 0 } else { self.pyramid.len() };
+    }
+
+    // The level images are currently scored at; 0 is full resolution
+    pub fn level(&self) -> usize {
+        self.level
+    }
+
+    pub fn level_count(&self) -> usize {
+        self.pyramid.len() + 1
+    }
+
+    // Moves scoring to the next finer level. Returns false if already at the
+    // finest.
+    pub fn promote(&mut self) -> bool {
+        if self.level == 0 {
+            return false;
+        }
+        self.level -= 1;
+        true
+    }
+
+    // Error of each pixel of an already rendered `image` against the full-size
+    // reference. Metrics without a per-pixel form (like SSIM) fall back to MSE.
+    pub fn error_map(&self, image: &RandomImage) -> Vec<f64> {
+        let shrunk = image.shrink(self.width, self.height);
+        self.metric
+            .pixel_errors(&shrunk.pixels, &self.values, self.width, self.height)
+            .unwrap_or_else(|| {
+                channel_errors(&shrunk.pixels, &self.values, channel_weights(false), |d| {
+                    d * d
+                })
+            })
+    }
+
+    // Something to steer mutations of `image` (already rendered) toward where
+    // it's most wrong, or None when `bias` is 0 and mutations are unguided
+    pub fn guide(&self, image: &RandomImage, bias: f64) -> Option<ErrorGuide> {
+        if bias > 0.0 {
+            Some(ErrorGuide::new(
+                self.error_map(image),
+                self.width,
+                self.height,
+                bias,
+            ))
+        } else {
+            None
+        }
+    }
+
+    // Renders `image`, scores it, and records the score as its fitness
+    pub fn evaluate(&self, image: &mut RandomImage) -> f64 {
+        image.errors.clear();
+        image.render();
+        self.rescore(image)
+    }
+
+    /*
+    `evaluate` for an image that was last evaluated here and has since changed
+    only inside `region` (canvas pixels, see `RandomImage::changed_region`;
+    None means anywhere). Only that region is re-rendered, and only the
+    reference pixels it shrinks into are re-scored: `errors` holds the image's
+    per-pixel errors from before the change, and is updated to match.
+    It starts over from a full shrink whenever `errors` doesn't fit (e.g. it's
+    empty, after a metric change), and falls back to `evaluate`'s full scoring,
+    leaving `errors` empty, for metrics that aren't a mean over pixels (SSIM,
+    PSNR) and with a pyramid.
+    */
+    pub(crate) fn evaluate_region(
+        &self,
+        image: &mut RandomImage,
+        region: Option<&BBox>,
+        errors: &mut Vec<f64>,
+    ) -> f64 {
+        match region {
+            Some(region) => image.render_region(region),
+            None => image.render(),
+        }
+        if !self.pyramid.is_empty() {
+            errors.clear();
+            return self.rescore(image);
+        }
+
+        let (width, height) = (self.width, self.height);
+        let updated = match region {
+            Some(region) if errors.len() == (width * height) as usize => {
+                self.update_errors(image, region, errors)
+            }
+            _ => {
+                let shrunk = image.shrink(width, height);
+                match self
+                    .metric
+                    .pixel_errors(&shrunk.pixels, &self.values, width, height)
+                {
+                    Some(all) => {
+                        *errors = all;
+                        true
+                    }
+                    None => false,
+                }
+            }
+        };
+        if !updated {
+            errors.clear();
+            return self.rescore(image);
+        }
+
+        // Same as the metric's `error`/`weighted_error`, given `pixel_errors`
+        let fitness = match self.weights {
+            Some(ref weights) => weighted_mean(errors, weights),
+            None => mean(errors),
+        };
+        image.fitness = fitness;
+        fitness
+    }
+
+    // Recomputes the entries of `errors` for the reference pixels whose tiles
+    // overlap `region`, a row at a time. False if the metric has no per-pixel
+    // errors.
+    fn update_errors(&self, image: &RandomImage, region: &BBox, errors: &mut [f64]) -> bool {
+        let (width, height) = (self.width, self.height);
+        let tile_width = image.width / width;
+        let tile_height = image.height / height;
+        // The tiles from the one holding the region's first pixel to the one
+        // holding its last
+        let x_end = ((region.xmax() - 1) / tile_width + 1).min(width);
+        let y_end = ((region.ymax() - 1) / tile_height + 1).min(height);
+        for y in (region.ymin() / tile_height)..y_end {
+            let x_min = region.xmin() / tile_width;
+            if x_min >= x_end {
+                break;
+            }
+            let pixels: Vec<Pixel> = (x_min..x_end)
+                .map(|x| image.tile_average(x, y, tile_width, tile_height))
+                .collect();
+            let start = (y * width + x_min) as usize;
+            let end = start + pixels.len();
+            let values = &self.values[start * 4..end * 4];
+            match self
+                .metric
+                .pixel_errors(&pixels, values, pixels.len() as u32, 1)
+            {
+                Some(row) => errors[start..end].copy_from_slice(&row),
+                None => return false,
+            }
+        }
+        true
+    }
+
+    // Scores an already rendered `image` and records the score as its fitness
+    fn rescore(&self, image: &mut RandomImage) -> f64 {
+        let fitness = if self.combine_levels {
+            let levels = self.level_count();
+            (0..levels).map(|l| self.score(image, l)).sum::<f64>() / levels as f64
+        } else {
+            self.score(image, self.level)
+        };
+        image.fitness = fitness;
+        fitness
+    }
+
+    // Scores an already rendered image at pyramid level `level`
+    fn score(&self, image: &RandomImage, level: usize) -> f64 {
+        let (values, width, height, weights) = if level == 0 {
+            (&self.values, self.width, self.height, &self.weights)
+        } else {
+            let l = &self.pyramid[level - 1];
+            (&l.values, l.width, l.height, &l.weights)
+        };
+        let shrunk = image.shrink(width, height);
+        match *weights {
+            Some(ref weights) => {
+                self.metric
+                    .weighted_error(&shrunk.pixels, values, weights, width, height)
+            }
+            None => self.metric.error(&shrunk.pixels, values, width, height),
+        }
+    }
+
+    fn resample_weights(&mut self) {
+        let (width, height) = (self.width, self.height);
+        for level in self.pyramid.iter_mut() {
+            level.weights = self
+                .weights
+                .as_ref()
+                .map(|weights| resample(weights, 1, width, height, level.width, level.height));
+        }
+    }
+}
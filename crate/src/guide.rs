@@ -0,0 +1,106 @@
+use nalgebra::Point2;
+use rand::Rng;
+use rand_pcg::Pcg32;
+use RandomImage;
+
+/*
+Steers mutations toward where an image is most wrong, using a per-pixel error
+map (see `Reference::error_map`) of the reference-sized image.
+Every choice is a mix: with probability `bias` it's drawn in proportion to
+error, otherwise uniformly, so a bias of 0 is the unguided baseline.
+*/
+pub struct ErrorGuide {
+    errors: Vec<f64>,
+    // Running totals of `errors`, for sampling a pixel in proportion to its error
+    cumulative: Vec<f64>,
+    width: u32,
+    height: u32,
+    bias: f64,
+}
+
+impl ErrorGuide {
+    pub fn new(errors: Vec<f64>, width: u32, height: u32, bias: f64) -> ErrorGuide {
+        let mut total = 0.0;
+        let cumulative = errors
+            .iter()
+            .map(|e| {
+                total += e.max(0.0);
+                total
+            })
+            .collect();
+        ErrorGuide {
+            errors,
+            cumulative,
+            width,
+            height,
+            bias,
+        }
+    }
+
+    fn guided(&self, rng: &mut Pcg32) -> bool {
+        self.bias > 0.0 && self.bias > rng.gen::<f64>()
+    }
+
+    // A point on a `canvas_width` x `canvas_height` canvas, favoring high error
+    pub fn sample_point(
+        &self,
+        canvas_width: u32,
+        canvas_height: u32,
+        rng: &mut Pcg32,
+    ) -> Point2<u32> {
+        let total = self.cumulative.last().cloned().unwrap_or(0.0);
+        if !self.guided(rng) || total.is_nan() || total <= 0.0 {
+            return Point2::new(
+                rng.gen_range(0, canvas_width),
+                rng.gen_range(0, canvas_height),
+            );
+        }
+
+        let target = rng.gen_range(0.0, total);
+        let idx = match self
+            .cumulative
+            .binary_search_by(|c| c.partial_cmp(&target).unwrap())
+        {
+            Ok(i) => i + 1,
+            Err(i) => i,
+        }
+        .min(self.errors.len() - 1) as u32;
+
+        // Anywhere inside the canvas tile that shrinks to that pixel
+        let tile_width = (canvas_width / self.width).max(1);
+        let tile_height = (canvas_height / self.height).max(1);
+        let (x, y) = (idx % self.width, idx / self.width);
+        Point2::new(
+            (x * tile_width + rng.gen_range(0, tile_width)).min(canvas_width - 1),
+            (y * tile_height + rng.gen_range(0, tile_height)).min(canvas_height - 1),
+        )
+    }
+
+    // The mean error under each of `image`'s genes. This rasterizes every
+    // gene, so compute it once per mutation event and pass it to `pick_gene`.
+    pub fn gene_errors(&self, image: &RandomImage) -> Vec<f64> {
+        image.gene_coverage_errors(&self.errors, self.width, self.height)
+    }
+
+    // Index of a gene to mutate, favoring genes that cover high error, given
+    // every gene's `gene_errors`
+    pub fn pick_gene(&self, gene_errors: &[f64], rng: &mut Pcg32) -> usize {
+        let count = gene_errors.len();
+        if !self.guided(rng) {
+            return rng.gen_range(0, count);
+        }
+
+        let total: f64 = gene_errors.iter().sum();
+        if total.is_nan() || total <= 0.0 {
+            return rng.gen_range(0, count);
+        }
+        let mut target = rng.gen_range(0.0, total);
+        for (i, e) in gene_errors.iter().enumerate() {
+            if target < *e {
+                return i;
+            }
+            target -= e;
+        }
+        count - 1
+    }
+}
@@ -0,0 +1,202 @@
+use fitness::metric_for;
+use single_image::SingleImage;
+use wasm_bindgen::prelude::*;
+use {ConfigError, EvolutionConfig, FitnessMetric, FitnessMetricKind, Pixel, RandomImage};
+
+/*
+(1+1) hill climbing over a single image: mutate one gene (or make one structural
+change), re-render and keep the change only if the error drops, otherwise put the
+old genes back.
+This converges much more reliably than `Population::evolve` because a good image
+is never made worse, so it is the mode to use for quality renders.
+*/
+#[wasm_bindgen]
+pub struct HillClimber {
+    search: SingleImage,
+    accepted: u32,
+    rejected: u32,
+    // 1/5th success rule state: the current multiplier on the configured
+    // jitter, and the successes within the current adaptation window
+    step_scale: f64,
+    window_accepted: u32,
+    window_steps: u32,
+}
+
+#[wasm_bindgen]
+impl HillClimber {
+    pub fn new(
+        width: u32,
+        height: u32,
+        reference: &[u8],
+        reference_w: u32,
+        reference_h: u32,
+    ) -> Self {
+        Self::new_with_config(
+            width,
+            height,
+            reference,
+            reference_w,
+            reference_h,
+            EvolutionConfig::default(),
+        )
+        .expect("the default config is valid")
+    }
+
+    // `reference` is the reference image's RGBA bytes, e.g. `imageData.data`
+    pub fn new_with_config(
+        width: u32,
+        height: u32,
+        reference: &[u8],
+        reference_w: u32,
+        reference_h: u32,
+        config: EvolutionConfig,
+    ) -> Result<HillClimber, JsValue> {
+        let climber = HillClimber::with_config(
+            width,
+            height,
+            reference.to_vec(),
+            reference_w,
+            reference_h,
+            config,
+        )?;
+        Ok(climber)
+    }
+
+    // Re-seeds and starts over from a fresh random image
+    pub fn reseed(&mut self, seed: u64) {
+        self.search.reseed(seed);
+        self.reset_counters();
+    }
+
+    // Takes one mutate/evaluate/keep-or-revert step. Returns whether the
+    // mutation was kept.
+    pub fn step(&mut self) -> bool {
+        let config = if self.search.config.one_fifth_rule {
+            self.search.config.with_step_scale(self.step_scale)
+        } else {
+            self.search.config
+        };
+        let previous_fitness = self.search.image.fitness;
+        let fitness = self.search.propose(&config);
+
+        let accepted = fitness < previous_fitness;
+        if accepted {
+            self.accepted += 1;
+        } else {
+            self.search.revert();
+            self.rejected += 1;
+        }
+        self.adapt_step_scale(accepted);
+        accepted
+    }
+
+    // Runs `steps` steps, returning how many were accepted
+    pub fn run(&mut self, steps: u32) -> u32 {
+        (0..steps).filter(|_| self.step()).count() as u32
+    }
+
+    pub fn set_fitness_metric(&mut self, kind: FitnessMetricKind, ignore_alpha: bool) {
+        self.set_metric(metric_for(kind, ignore_alpha));
+    }
+
+    pub fn fitness(&self) -> f64 {
+        self.search.image.fitness
+    }
+
+    pub fn accepted(&self) -> u32 {
+        self.accepted
+    }
+
+    pub fn rejected(&self) -> u32 {
+        self.rejected
+    }
+
+    pub fn steps(&self) -> u32 {
+        self.accepted + self.rejected
+    }
+
+    // Current 1/5th-rule multiplier on the configured vertex/color jitter
+    pub fn step_scale(&self) -> f64 {
+        self.step_scale
+    }
+
+    pub fn pixels(&self) -> *const Pixel {
+        self.search.image.pixels()
+    }
+
+    pub fn genome_json(&self) -> String {
+        self.search.image.genome_json()
+    }
+
+    // The current image drawn at another size, e.g. for printing
+    pub fn render_at(&self, width: u32, height: u32) -> RandomImage {
+        self.search.image.render_at(width, height)
+    }
+}
+
+impl HillClimber {
+    pub fn with_config(
+        width: u32,
+        height: u32,
+        ref_values: Vec<u8>,
+        reference_w: u32,
+        reference_h: u32,
+        config: EvolutionConfig,
+    ) -> Result<HillClimber, ConfigError> {
+        Ok(HillClimber {
+            search: SingleImage::new(width, height, ref_values, reference_w, reference_h, config)?,
+            accepted: 0,
+            rejected: 0,
+            step_scale: 1.0,
+            window_accepted: 0,
+            window_steps: 0,
+        })
+    }
+
+    pub fn image(&self) -> &RandomImage {
+        &self.search.image
+    }
+
+    pub fn set_metric(&mut self, metric: Box<dyn FitnessMetric>) {
+        self.search.set_metric(metric);
+    }
+
+    fn reset_counters(&mut self) {
+        self.accepted = 0;
+        self.rejected = 0;
+        self.step_scale = 1.0;
+        self.window_accepted = 0;
+        self.window_steps = 0;
+    }
+
+    /*
+    Rechenberg's 1/5th success rule: if more than a fifth of recent mutations
+    improved the image the steps are too timid, so grow them; if fewer, the
+    steps are overshooting, so shrink them.
+    */
+    fn adapt_step_scale(&mut self, accepted: bool) {
+        if !self.search.config.one_fifth_rule {
+            return;
+        }
+        self.window_steps += 1;
+        if accepted {
+            self.window_accepted += 1;
+        }
+        if self.window_steps < self.search.config.adaptation_interval {
+            return;
+        }
+
+        let success_rate = self.window_accepted as f64 / self.window_steps as f64;
+        if success_rate > 0.2 {
+            self.step_scale /= self.search.config.adaptation_factor;
+        } else if success_rate < 0.2 {
+            self.step_scale *= self.search.config.adaptation_factor;
+        }
+        self.step_scale = self
+            .step_scale
+            .max(self.search.config.min_step_scale)
+            .min(self.search.config.max_step_scale);
+        self.window_accepted = 0;
+        self.window_steps = 0;
+    }
+}
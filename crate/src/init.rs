@@ -0,0 +1,292 @@
+use fitness::{edge_weights, Reference};
+use nalgebra::Point2;
+use rand_pcg::Pcg32;
+use shape::{Geometry, Shape, ShapeKind, Triangle};
+use wasm_bindgen::prelude::*;
+use {Color, EvolutionConfig, Gene, Gradient};
+
+/*
+How a new image's genes are chosen.
+`Random` scatters random triangles with random colors.
+`CentroidColor` scatters random triangles, but colors each one with the
+reference's color under its centroid.
+`Delaunay` tiles the canvas with a Delaunay triangulation of points on the
+reference's strongest edges and corners, each triangle colored from the
+reference, so the image starts as a low-poly version of it. It needs
+triangles, so with another `ShapeKind` (other than `Mixed`) it acts like
+`CentroidColor`.
+*/
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub enum InitStrategy {
+    Random,
+    CentroidColor,
+    Delaunay,
+}
+
+/*
+Where an image's background color comes from.
+`Evolve` makes it part of the genome: it starts white (or on the reference's
+mean color, with `init_mean_background`) and is mutated and crossed over like
+the genes. The others fix it for the whole run, to
+`EvolutionConfig::background_color` or the reference's mean or median color.
+*/
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub enum BackgroundMode {
+    Evolve,
+    Fixed,
+    Mean,
+    Median,
+}
+
+// Without a reference, the reference-derived colors fall back to white
+pub fn initial_background(reference: Option<&Reference>, config: &EvolutionConfig) -> Color {
+    match (config.background_mode, reference) {
+        (BackgroundMode::Fixed, _) => Color {
+            r: (config.background_color >> 16) as u8,
+            g: (config.background_color >> 8) as u8,
+            b: config.background_color as u8,
+            a: 255,
+        },
+        (BackgroundMode::Evolve, Some(reference)) if config.init_mean_background => {
+            mean_color(&reference.values)
+        }
+        (BackgroundMode::Mean, Some(reference)) => mean_color(&reference.values),
+        (BackgroundMode::Median, Some(reference)) => median_color(&reference.values),
+        _ => Color::white(),
+    }
+}
+
+pub fn initial_genes(
+    width: u32,
+    height: u32,
+    reference: &Reference,
+    config: &EvolutionConfig,
+    rng: &mut Pcg32,
+) -> Vec<Gene> {
+    let kind = config.shape_kind;
+    let triangles = kind == ShapeKind::Triangle || kind == ShapeKind::Mixed;
+    match config.init_strategy {
+        InitStrategy::Random => (0..config.gene_count)
+            .map(|_| Gene::random(config, width, height, rng))
+            .collect(),
+        InitStrategy::Delaunay if triangles => {
+            let mut genes = delaunay_genes(width, height, reference, config, rng);
+            genes.truncate(config.max_genes as usize);
+            while genes.len() < config.min_genes as usize {
+                let gene = Gene::random(config, width, height, rng);
+                genes.push(centroid_colored(gene, reference));
+            }
+            genes
+        }
+        InitStrategy::CentroidColor | InitStrategy::Delaunay => (0..config.gene_count)
+            .map(|_| {
+                let gene = Gene::random(config, width, height, rng);
+                centroid_colored(gene, reference)
+            })
+            .collect(),
+    }
+}
+
+// The reference's average color, fully opaque
+pub fn mean_color(values: &[u8]) -> Color {
+    let count = (values.len() / 4).max(1) as u64;
+    let mut sums = [0u64; 3];
+    for v in values.chunks(4) {
+        for c in 0..3 {
+            sums[c] += v[c] as u64;
+        }
+    }
+    Color {
+        r: (sums[0] / count) as u8,
+        g: (sums[1] / count) as u8,
+        b: (sums[2] / count) as u8,
+        a: 255,
+    }
+}
+
+// The reference's per-channel median color, fully opaque. Unlike the mean it
+// isn't pulled toward the colors of small, saturated details.
+pub fn median_color(values: &[u8]) -> Color {
+    let mut histograms = [[0u32; 256]; 3];
+    for v in values.chunks(4) {
+        for c in 0..3 {
+            histograms[c][v[c] as usize] += 1;
+        }
+    }
+    let count = values.len() / 4;
+    let half = count - count / 2;
+    let median = |histogram: &[u32; 256]| {
+        let mut seen = 0;
+        for (value, &count) in histogram.iter().enumerate() {
+            seen += count as usize;
+            if seen >= half {
+                return value as u8;
+            }
+        }
+        255
+    };
+    Color {
+        r: median(&histograms[0]),
+        g: median(&histograms[1]),
+        b: median(&histograms[2]),
+        a: 255,
+    }
+}
+
+// The reference's color at position (x, y), as fractions of the canvas
+fn sample_color(x: f64, y: f64, reference: &Reference) -> (u8, u8, u8) {
+    let rx = ((x.max(0.0) * reference.width as f64) as u32).min(reference.width - 1);
+    let ry = ((y.max(0.0) * reference.height as f64) as u32).min(reference.height - 1);
+    let i = ((ry * reference.width + rx) * 4) as usize;
+    let v = &reference.values[i..i + 3];
+    (v[0], v[1], v[2])
+}
+
+// Recolors `gene` with the reference color under its center, keeping its alpha
+fn centroid_colored(mut gene: Gene, reference: &Reference) -> Gene {
+    let (cx, cy) = gene.geometry.shape().center();
+    let (r, g, b) = sample_color(cx, cy, reference);
+    gene.color.r = r;
+    gene.color.g = g;
+    gene.color.b = b;
+    gene
+}
+
+/*
+Triangulates the canvas corners plus points picked from the reference's Sobel
+edge map (strongest first, skipping any too close to one already picked), with
+about enough points to make `gene_count` triangles. The triangles are opaque,
+since they don't overlap. Each gets a gradient of `gradient_kind` whose stops
+both start out as its color, so it only turns into a gradient as it evolves.
+*/
+fn delaunay_genes(
+    width: u32,
+    height: u32,
+    reference: &Reference,
+    config: &EvolutionConfig,
+    rng: &mut Pcg32,
+) -> Vec<Gene> {
+    let gene_count = config.gene_count;
+    let (ref_w, ref_h) = (reference.width, reference.height);
+    // A triangulation of n points has about 2n triangles
+    let point_count = (gene_count as usize / 2 + 3).max(4);
+    let spacing = ((ref_w * ref_h) as f64 / point_count as f64).sqrt() / 2.0;
+
+    let edges = edge_weights(&reference.values, ref_w, ref_h, 0.0);
+    let mut order: Vec<usize> = (0..edges.len()).collect();
+    order.sort_by(|&a, &b| edges[b].partial_cmp(&edges[a]).unwrap());
+
+    // In the reference's pixels, so the triangulation sees its true aspect
+    let (max_x, max_y) = (ref_w as f64, ref_h as f64);
+    let mut points = vec![(0.0, 0.0), (max_x, 0.0), (0.0, max_y), (max_x, max_y)];
+    let mut picked: Vec<(f64, f64)> = vec![];
+    for i in order {
+        if points.len() >= point_count {
+            break;
+        }
+        let (x, y) = ((i as u32 % ref_w) as f64, (i as u32 / ref_w) as f64);
+        let far_enough = picked
+            .iter()
+            .all(|&(px, py)| (px - x).powi(2) + (py - y).powi(2) >= spacing * spacing);
+        if far_enough {
+            picked.push((x, y));
+            points.push((x + 0.5, y + 0.5));
+        }
+    }
+
+    let vertex = |i: usize| Point2::new((points[i].0 / max_x) as f32, (points[i].1 / max_y) as f32);
+    delaunay(&points)
+        .iter()
+        .map(|t| {
+            let cx = (points[t[0]].0 + points[t[1]].0 + points[t[2]].0) / 3.0;
+            let cy = (points[t[0]].1 + points[t[1]].1 + points[t[2]].1) / 3.0;
+            let (r, g, b) = sample_color(cx / max_x, cy / max_y, reference);
+            let color = Color { r, g, b, a: 255 };
+            let triangle = Triangle::new(vertex(t[0]), vertex(t[1]), vertex(t[2]));
+            let bbox = triangle.bbox(width, height);
+            let gradient = Gradient::random(config.gradient_kind, &bbox, width, height, rng).map(
+                |mut gradient| {
+                    *gradient.color_mut() = color.clone();
+                    gradient
+                },
+            );
+            Gene {
+                geometry: Geometry::Triangle(triangle),
+                color,
+                step_scale: 1.0,
+                gradient,
+            }
+        })
+        .collect()
+}
+
+/*
+Bowyer-Watson: add points one at a time to a triangulation that starts as one
+huge triangle around them all. Each new point replaces the triangles whose
+circumcircles contain it with a fan of triangles from the hole's edges to it.
+Returns triangles as indices into `points`.
+*/
+fn delaunay(points: &[(f64, f64)]) -> Vec<[usize; 3]> {
+    let n = points.len();
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (0.0f64, 0.0f64, 0.0f64, 0.0f64);
+    for &(x, y) in points {
+        min_x = min_x.min(x);
+        min_y = min_y.min(y);
+        max_x = max_x.max(x);
+        max_y = max_y.max(y);
+    }
+    let d = (max_x - min_x).max(max_y - min_y) * 10.0 + 1.0;
+    let (mid_x, mid_y) = ((min_x + max_x) / 2.0, (min_y + max_y) / 2.0);
+
+    let mut pts = points.to_vec();
+    pts.push((mid_x - 2.0 * d, mid_y - d));
+    pts.push((mid_x, mid_y + 2.0 * d));
+    pts.push((mid_x + 2.0 * d, mid_y - d));
+
+    let mut triangles = vec![[n, n + 1, n + 2]];
+    for i in 0..n {
+        let p = pts[i];
+        let (bad, good): (Vec<[usize; 3]>, Vec<[usize; 3]>) = triangles
+            .into_iter()
+            .partition(|t| in_circumcircle(pts[t[0]], pts[t[1]], pts[t[2]], p));
+
+        // Edges of the hole are the ones only one bad triangle has
+        let mut edges: Vec<(usize, usize)> = vec![];
+        for t in bad.iter() {
+            for &(a, b) in [(t[0], t[1]), (t[1], t[2]), (t[2], t[0])].iter() {
+                match edges
+                    .iter()
+                    .position(|&(c, d)| (c == a && d == b) || (c == b && d == a))
+                {
+                    Some(shared) => {
+                        edges.remove(shared);
+                    }
+                    None => edges.push((a, b)),
+                }
+            }
+        }
+
+        triangles = good;
+        triangles.extend(edges.iter().map(|&(a, b)| [a, b, i]));
+    }
+
+    triangles.retain(|t| t.iter().all(|&v| v < n));
+    triangles
+}
+
+fn in_circumcircle(a: (f64, f64), b: (f64, f64), c: (f64, f64), p: (f64, f64)) -> bool {
+    let (ax, ay) = (a.0 - p.0, a.1 - p.1);
+    let (bx, by) = (b.0 - p.0, b.1 - p.1);
+    let (cx, cy) = (c.0 - p.0, c.1 - p.1);
+    let det = (ax * ax + ay * ay) * (bx * cy - cx * by) - (bx * bx + by * by) * (ax * cy - cx * ay)
+        + (cx * cx + cy * cy) * (ax * by - bx * ay);
+    // The sign of the determinant flips with the triangle's winding
+    let orientation = (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0);
+    if orientation > 0.0 {
+        det > 0.0
+    } else {
+        det < 0.0
+    }
+}
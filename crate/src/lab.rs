@@ -0,0 +1,257 @@
+use fitness::{mean, FitnessMetric};
+use std::f64::consts::PI;
+use Pixel;
+
+// A color in CIELAB space (D65 white point): L is lightness in [0, 100], a and
+// b are the green-red and blue-yellow axes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Lab {
+    pub l: f64,
+    pub a: f64,
+    pub b: f64,
+}
+
+// D65 reference white, in XYZ
+const WHITE: [f64; 3] = [0.95047, 1.0, 1.08883];
+
+// Undoes the sRGB gamma curve, giving linear light in [0, 1]
+fn srgb_to_linear(v: u8) -> f64 {
+    let c = v as f64 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn lab_f(t: f64) -> f64 {
+    const DELTA: f64 = 6.0 / 29.0;
+    if t > DELTA * DELTA * DELTA {
+        t.cbrt()
+    } else {
+        t / (3.0 * DELTA * DELTA) + 4.0 / 29.0
+    }
+}
+
+impl Lab {
+    pub fn from_srgb(r: u8, g: u8, b: u8) -> Lab {
+        let (r, g, b) = (srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b));
+        let x = 0.4124564 * r + 0.3575761 * g + 0.1804375 * b;
+        let y = 0.2126729 * r + 0.7151522 * g + 0.0721750 * b;
+        let z = 0.0193339 * r + 0.1191920 * g + 0.9503041 * b;
+
+        let (fx, fy, fz) = (
+            lab_f(x / WHITE[0]),
+            lab_f(y / WHITE[1]),
+            lab_f(z / WHITE[2]),
+        );
+        Lab {
+            l: 116.0 * fy - 16.0,
+            a: 500.0 * (fx - fy),
+            b: 200.0 * (fy - fz),
+        }
+    }
+
+    // CIE76: plain euclidean distance in Lab
+    pub fn delta_e76(&self, other: &Lab) -> f64 {
+        ((self.l - other.l).powi(2) + (self.a - other.a).powi(2) + (self.b - other.b).powi(2))
+            .sqrt()
+    }
+
+    /*
+    CIEDE2000, which corrects CIE76 for the eye being less sensitive to
+    differences in saturated colors and more sensitive to hue shifts, especially
+    in blues. Follows Sharma, Wu & Dalal, "The CIEDE2000 color-difference
+    formula: implementation notes" (2005), with kL = kC = kH = 1.
+    */
+    pub fn delta_e2000(&self, other: &Lab) -> f64 {
+        let (l1, a1, b1) = (self.l, self.a, self.b);
+        let (l2, a2, b2) = (other.l, other.a, other.b);
+
+        let c_mean = ((a1 * a1 + b1 * b1).sqrt() + (a2 * a2 + b2 * b2).sqrt()) / 2.0;
+        let c_mean7 = c_mean.powi(7);
+        let g = 0.5 * (1.0 - (c_mean7 / (c_mean7 + 25f64.powi(7))).sqrt());
+        let (a1, a2) = (a1 * (1.0 + g), a2 * (1.0 + g));
+        let (c1, c2) = ((a1 * a1 + b1 * b1).sqrt(), (a2 * a2 + b2 * b2).sqrt());
+        let hue = |b: f64, a: f64| {
+            if a == 0.0 && b == 0.0 {
+                0.0
+            } else {
+                let h = b.atan2(a).to_degrees();
+                if h < 0.0 {
+                    h + 360.0
+                } else {
+                    h
+                }
+            }
+        };
+        let (h1, h2) = (hue(b1, a1), hue(b2, a2));
+
+        let dl = l2 - l1;
+        let dc = c2 - c1;
+        let dh = if c1 * c2 == 0.0 {
+            0.0
+        } else if (h2 - h1).abs() <= 180.0 {
+            h2 - h1
+        } else if h2 - h1 > 180.0 {
+            h2 - h1 - 360.0
+        } else {
+            h2 - h1 + 360.0
+        };
+        let dh = 2.0 * (c1 * c2).sqrt() * (dh / 2.0).to_radians().sin();
+
+        let l_mean = (l1 + l2) / 2.0;
+        let c_mean = (c1 + c2) / 2.0;
+        let h_mean = if c1 * c2 == 0.0 {
+            h1 + h2
+        } else if (h1 - h2).abs() <= 180.0 {
+            (h1 + h2) / 2.0
+        } else if h1 + h2 < 360.0 {
+            (h1 + h2 + 360.0) / 2.0
+        } else {
+            (h1 + h2 - 360.0) / 2.0
+        };
+
+        let t = 1.0 - 0.17 * (h_mean - 30.0).to_radians().cos()
+            + 0.24 * (2.0 * h_mean).to_radians().cos()
+            + 0.32 * (3.0 * h_mean + 6.0).to_radians().cos()
+            - 0.20 * (4.0 * h_mean - 63.0).to_radians().cos();
+        let d_theta = 30.0 * (-((h_mean - 275.0) / 25.0).powi(2)).exp();
+        let c_mean7 = c_mean.powi(7);
+        let r_c = 2.0 * (c_mean7 / (c_mean7 + 25f64.powi(7))).sqrt();
+        let s_l = 1.0 + 0.015 * (l_mean - 50.0).powi(2) / (20.0 + (l_mean - 50.0).powi(2)).sqrt();
+        let s_c = 1.0 + 0.045 * c_mean;
+        let s_h = 1.0 + 0.015 * c_mean * t;
+        let r_t = -(2.0 * d_theta * PI / 180.0).sin() * r_c;
+
+        ((dl / s_l).powi(2)
+            + (dc / s_c).powi(2)
+            + (dh / s_h).powi(2)
+            + r_t * (dc / s_c) * (dh / s_h))
+            .sqrt()
+    }
+}
+
+// Converts RGBA bytes, 4 per pixel, to Lab (alpha is ignored)
+pub fn lab_values(values: &[u8]) -> Vec<Lab> {
+    values
+        .chunks(4)
+        .map(|v| Lab::from_srgb(v[0], v[1], v[2]))
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeltaEFormula {
+    Cie76,
+    Ciede2000,
+}
+
+/*
+Mean perceptual color difference (ΔE) between each pixel and the reference.
+A ΔE around 1 is about the smallest difference people notice, so unlike RGB
+error this doesn't waste effort on invisible differences.
+The reference (and each pyramid level) is converted to Lab once, in `prepare`,
+rather than on every evaluation.
+*/
+pub struct DeltaE {
+    pub formula: DeltaEFormula,
+    prepared: Vec<PreparedReference>,
+}
+
+/*
+A prepared reference's Lab colors, found again by where its bytes are rather
+than by its size, which different references (and rows of one) can share.
+Any part of it, such as a row, is found the same way.
+*/
+struct PreparedReference {
+    start: usize,
+    len: usize,
+    labs: Vec<Lab>,
+}
+
+impl PreparedReference {
+    fn labs_of(&self, reference: &[u8]) -> Option<&[Lab]> {
+        let start = reference.as_ptr() as usize;
+        // Parts of a reference, like its rows, start on a pixel
+        if start < self.start || start + reference.len() > self.start + self.len {
+            return None;
+        }
+        let first = (start - self.start) / 4;
+        Some(&self.labs[first..first + reference.len() / 4])
+    }
+}
+
+impl DeltaE {
+    pub fn new(formula: DeltaEFormula) -> DeltaE {
+        DeltaE {
+            formula,
+            prepared: vec![],
+        }
+    }
+}
+
+impl DeltaE {
+    fn differences(&self, pixels: &[Pixel], reference: &[u8]) -> Vec<f64> {
+        if pixels.len() * 4 != reference.len() {
+            panic!(
+                "Got bad sizes for compare: {} pixels vs {} reference values",
+                pixels.len(),
+                reference.len()
+            );
+        }
+
+        // Only converted here without a matching `prepare`, e.g. when called
+        // directly
+        let converted;
+        let reference_lab = match self
+            .prepared
+            .iter()
+            .find_map(|prepared| prepared.labs_of(reference))
+        {
+            Some(labs) => labs,
+            None => {
+                converted = lab_values(reference);
+                &converted[..]
+            }
+        };
+
+        pixels
+            .iter()
+            .zip(reference_lab.iter())
+            .map(|(pixel, target)| {
+                let lab = Lab::from_srgb(pixel.r, pixel.g, pixel.b);
+                match self.formula {
+                    DeltaEFormula::Cie76 => lab.delta_e76(target),
+                    DeltaEFormula::Ciede2000 => lab.delta_e2000(target),
+                }
+            })
+            .collect()
+    }
+}
+
+impl FitnessMetric for DeltaE {
+    fn error(&self, pixels: &[Pixel], reference: &[u8], _width: u32, _height: u32) -> f64 {
+        mean(&self.differences(pixels, reference))
+    }
+
+    fn prepare(&mut self, references: &[&[u8]]) {
+        self.prepared = references
+            .iter()
+            .map(|reference| PreparedReference {
+                start: reference.as_ptr() as usize,
+                len: reference.len(),
+                labs: lab_values(reference),
+            })
+            .collect();
+    }
+
+    fn pixel_errors(
+        &self,
+        pixels: &[Pixel],
+        reference: &[u8],
+        _width: u32,
+        _height: u32,
+    ) -> Option<Vec<f64>> {
+        Some(self.differences(pixels, reference))
+    }
+}
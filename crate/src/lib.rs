@@ -2,13 +2,55 @@
 extern crate cfg_if;
 extern crate nalgebra;
 extern crate rand;
+extern crate rand_pcg;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
 extern crate wasm_bindgen;
 
-use nalgebra::{Point2, Vector3};
+mod anneal;
+mod config;
+mod distribution;
+mod fitness;
+mod guide;
+mod hill_climber;
+mod init;
+mod lab;
+mod pyramid;
+mod shape;
+mod single_image;
+mod ssim;
+
+pub use anneal::{
+    AdaptiveCooling, AnnealingStep, CoolingSchedule, ExponentialCooling, LinearCooling,
+    SimulatedAnnealing,
+};
+pub use config::{ConfigError, EvolutionConfig};
+pub use distribution::{apply_boundary, random_step, BoundaryMode, MutationDistribution};
+pub use fitness::{FitnessMetric, FitnessMetricKind, Mae, Mse, Psnr, WeightedMse, MAX_PSNR};
+pub use hill_climber::HillClimber;
+pub use init::{BackgroundMode, InitStrategy};
+pub use lab::{DeltaE, DeltaEFormula, Lab};
+pub use shape::{BBox, Circle, FillRule, Polygon, RotatedRect, Shape, ShapeKind, Triangle};
+pub use ssim::{MsSsim, Ssim};
+
+use distribution::perturb;
+use fitness::{edge_weights, heatmap, metric_for, Reference};
+pub use guide::ErrorGuide;
+use init::{initial_background, initial_genes};
+use nalgebra::Point2;
+use rand::distributions::StandardNormal;
 use rand::rngs::OsRng;
-use rand::Rng;
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg32;
+use shape::{
+    jitter_length, jitter_point, pixel_center, pixel_position, random_length, scale, to_pixels,
+    Geometry,
+};
 use std::cmp::Ordering;
-use std::slice;
+use std::collections::BTreeMap;
+use std::mem;
 use wasm_bindgen::prelude::*;
 
 // A macro to provide `println!(..)`-style syntax for `console.log` logging.
@@ -50,26 +92,31 @@ fn clamp(v: f64, min: f64, max: f64) -> f64 {
     }
 }
 
-fn clamped_rand_range(
-    v: f64,
-    vary_width: f64,
-    min: f64,
-    max: f64,
-    rng: &mut rand::rngs::OsRng,
-) -> f64 {
-    let lo = clamp(v - vary_width, min, max);
-    let hi = clamp(v + vary_width, min, max);
-    rng.gen_range(lo, hi)
+// Two different random indices below `len`, which must be at least 2
+fn distinct_indices(len: usize, rng: &mut Pcg32) -> (usize, usize) {
+    let a = rng.gen_range(0, len);
+    let b = rng.gen_range(0, len - 1);
+    (a, if b >= a { b + 1 } else { b })
+}
+
+// For callers that don't care about reproducibility (e.g. the wasm-bindgen
+// convenience methods on `RandomImage`), seed a fresh RNG from the OS.
+fn entropy_rng() -> Pcg32 {
+    Pcg32::from_rng(OsRng::new().unwrap()).unwrap()
 }
 
 #[wasm_bindgen]
 pub struct Population {
     width: u32,
     height: u32,
-    ref_values: Vec<u8>,
     members: Vec<RandomImage>,
-    reference_w: u32,
-    reference_h: u32,
+    reference: Reference,
+    config: EvolutionConfig,
+    rng: Pcg32,
+    // Coarse-to-fine state: the best fitness seen at the current pyramid level,
+    // and how many generations it has gone without improving
+    level_best: f64,
+    stalled_generations: u32,
 }
 
 #[wasm_bindgen]
@@ -77,60 +124,243 @@ impl Population {
     pub fn new(
         width: u32,
         height: u32,
-        rev_values_ptr: *mut u8,
+        reference: &[u8],
         reference_w: u32,
         reference_h: u32,
     ) -> Self {
-        let reference_values_count = reference_w * reference_h * 4;
-        let ref_values =
-            unsafe { slice::from_raw_parts(rev_values_ptr, reference_values_count as usize) };
-        Self {
+        Self::new_with_config(
             width,
             height,
-            ref_values: ref_values.to_vec(),
-            members: vec![],
+            reference,
             reference_w,
             reference_h,
+            EvolutionConfig::default(),
+        )
+        .expect("the default config is valid")
+    }
+
+    // `reference` is the reference image's RGBA bytes, e.g. `imageData.data`
+    pub fn new_with_config(
+        width: u32,
+        height: u32,
+        reference: &[u8],
+        reference_w: u32,
+        reference_h: u32,
+        config: EvolutionConfig,
+    ) -> Result<Population, JsValue> {
+        let population = Population::with_config(
+            width,
+            height,
+            reference.to_vec(),
+            reference_w,
+            reference_h,
+            config,
+        )?;
+        Ok(population)
+    }
+
+    pub fn new_with_seed(
+        width: u32,
+        height: u32,
+        reference: &[u8],
+        reference_w: u32,
+        reference_h: u32,
+        seed: u64,
+    ) -> Self {
+        let mut population = Self::new(width, height, reference, reference_w, reference_h);
+        population.reseed(seed);
+        population
+    }
+
+    // Two populations with the same seed, config and reference evolve identically
+    pub fn reseed(&mut self, seed: u64) {
+        self.rng = Pcg32::seed_from_u64(seed);
+    }
+
+    pub fn config(&self) -> EvolutionConfig {
+        self.config
+    }
+
+    pub fn set_config(&mut self, config: EvolutionConfig) -> Result<(), JsValue> {
+        config.validate()?;
+        self.config = config;
+        Ok(())
+    }
+
+    pub fn set_tournament_size(&mut self, tournament_size: u32) -> Result<(), JsValue> {
+        let config = EvolutionConfig {
+            tournament_size,
+            ..self.config
+        };
+        self.set_config(config)
+    }
+
+    pub fn set_crossover_rate(&mut self, crossover_rate: f64) -> Result<(), JsValue> {
+        let config = EvolutionConfig {
+            crossover_rate,
+            ..self.config
+        };
+        self.set_config(config)
+    }
+
+    pub fn set_crossover_type(&mut self, crossover_type: CrossoverType) {
+        self.config.crossover_type = crossover_type;
+    }
+
+    pub fn set_fitness_metric(&mut self, kind: FitnessMetricKind, ignore_alpha: bool) {
+        self.set_metric(metric_for(kind, ignore_alpha));
+    }
+
+    // Per-channel weighted MSE; pass a = 0 to ignore alpha
+    pub fn set_channel_weights(&mut self, r: f64, g: f64, b: f64, a: f64) -> Result<(), JsValue> {
+        self.set_metric(Box::new(WeightedMse::new([r, g, b, a])?));
+        Ok(())
+    }
+
+    // SSIM with a `window` x `window` window and stabilizing constants k1/k2
+    // (defaults 7, 0.01, 0.03). `scales` > 1 uses multi-scale SSIM.
+    // `mse_weight` in [0, 1] blends in MSE.
+    pub fn set_ssim_metric(
+        &mut self,
+        window: u32,
+        k1: f64,
+        k2: f64,
+        scales: u32,
+        mse_weight: f64,
+    ) -> Result<(), JsValue> {
+        let metric: Box<dyn FitnessMetric> = match scales {
+            0 => return Err(ConfigError::ZeroSsimScales.into()),
+            1 => Box::new(Ssim::new(window, k1, k2, mse_weight)?),
+            _ => Box::new(MsSsim::new(window, k1, k2, scales, mse_weight)?),
+        };
+        self.set_metric(metric);
+        Ok(())
+    }
+
+    // Makes some pixels count more than others, e.g. a face more than the
+    // background. `weights` has one non-negative value per reference pixel.
+    pub fn set_weight_map(&mut self, weights: Vec<f64>) -> Result<(), JsValue> {
+        self.set_weights(Some(weights))?;
+        Ok(())
+    }
+
+    // Weights pixels by how edgy the reference is around them (Sobel gradient
+    // magnitude), with flat areas weighted `floor` relative to the strongest edge
+    pub fn use_edge_weight_map(&mut self, floor: f64) {
+        let floor = clamp(floor, 0.0, 1.0);
+        let weights = edge_weights(
+            &self.reference.values,
+            self.reference.width,
+            self.reference.height,
+            floor,
+        );
+        // Can only fail if the reference is completely flat and floor is 0
+        if self.set_weights(Some(weights)).is_err() {
+            self.clear_weight_map();
         }
     }
 
+    pub fn clear_weight_map(&mut self) {
+        self.set_weights(None).unwrap();
+    }
+
+    /*
+    Scores members against a Gaussian pyramid of the reference: `levels`
+    successively blurred and halved copies, starting at the coarsest, where
+    broad shapes and colors are cheap to get right. Whenever progress stalls
+    (see `pyramid_patience`) scoring moves to the next finer level.
+    With `combined`, every member is instead scored at all levels at once.
+    Genes are in canvas coordinates, so they carry over between levels as is.
+    */
+    pub fn use_pyramid(&mut self, levels: u32, combined: bool) {
+        self.reference
+            .set_pyramid(levels, self.width, self.height, combined);
+        self.level_best = f64::INFINITY;
+        self.stalled_generations = 0;
+        self.reevaluate();
+    }
+
+    // The pyramid level members are being scored at; 0 is full resolution
+    pub fn pyramid_level(&self) -> u32 {
+        self.reference.level() as u32
+    }
+
+    pub fn pyramid_levels(&self) -> u32 {
+        self.reference.level_count() as u32
+    }
+
     pub fn evolve(&mut self) {
-        let mut rng = OsRng::new().unwrap();
-        let mutate_percent = 0.5;
-        let cull_percent = 0.25;
+        let config = self.config;
+        let rng = &mut self.rng;
 
         self.members.sort();
-        let fitnesses = self.members.iter().map(|m| m.fitness).collect::<Vec<f64>>();
-        // log!("fitnesses, sorted: {:?}", fitnesses);
-
-        // let genes = self.members.iter().map(|m| m.genes).collect();
 
-        // drop the worst-performers, mutate remaining, add new members
+        // drop the worst-performers, breed replacements from the survivors,
+        // mutate remaining
 
-        let cull_count = ((self.members.len() as f64) * cull_percent) as u8;
+        let cull_count = ((self.members.len() as f64) * config.cull_fraction) as usize;
         for _ in 0..cull_count {
             self.members.pop();
         }
 
-        for m in &mut self.members {
-            if mutate_percent > rng.gen::<f64>() {
-                m.mutate();
-                m.calculate_fitness(&self.ref_values, self.reference_w, self.reference_h);
+        // Mutations are steered toward where the current best is most wrong
+        let reference = &self.reference;
+        let guide = self
+            .members
+            .first()
+            .and_then(|best| reference.guide(best, config.error_bias));
+        let guide = guide.as_ref();
+
+        let mut offspring = Vec::with_capacity(cull_count);
+        for _ in 0..cull_count {
+            let parent = tournament_select(&self.members, config.tournament_size, rng);
+            let mut child = self.members[parent].clone();
+            if config.crossover_rate > rng.gen::<f64>() {
+                let other = tournament_select(&self.members, config.tournament_size, rng);
+                child.breed_with_rng(&self.members[other], config.crossover_type, rng);
+                // Parents from before a `set_config` that lowered the bound
+                // can breed a child over it
+                child.genes.truncate(config.max_genes as usize);
             }
+            if config.mutation_rate > rng.gen::<f64>() {
+                child.mutate_guided(&config, guide, rng);
+            }
+            if config.color_solve_rate > rng.gen::<f64>() {
+                child.color_solve_step(reference, guide, rng);
+            }
+            let parent = &self.members[parent];
+            child.reevaluate_changes(reference, &parent.genes, &parent.background);
+            offspring.push(child);
         }
-        for _ in 0..cull_count {
-            self.add_member();
+
+        for m in &mut self.members {
+            let (genes, background) = (m.genes.clone(), m.background.clone());
+            if config.mutation_rate > rng.gen::<f64>() {
+                m.mutate_guided(&config, guide, rng);
+            }
+            if config.color_solve_rate > rng.gen::<f64>() {
+                m.color_solve_step(reference, guide, rng);
+            }
+            m.reevaluate_changes(reference, &genes, &background);
         }
+        self.members.extend(offspring);
+        self.update_pyramid_level();
     }
 
     pub fn add_member(&mut self) {
-        let mut member = RandomImage::new(self.width, self.height);
-        member.calculate_fitness(&self.ref_values, self.reference_w, self.reference_h);
+        let mut member = RandomImage::new_from_reference(
+            self.width,
+            self.height,
+            &self.reference,
+            &self.config,
+            &mut self.rng,
+        );
+        self.reference.evaluate(&mut member);
         self.members.push(member);
     }
 
     pub fn best_fitness(&mut self) -> f64 {
-        if self.members.len() == 0 {
+        if self.members.is_empty() {
             0.0
         } else {
             self.members.sort();
@@ -142,6 +372,138 @@ impl Population {
         self.members.sort();
         self.members.first().unwrap().pixels()
     }
+
+    pub fn best_genome_json(&mut self) -> String {
+        self.members.sort();
+        self.members.first().unwrap().genome_json()
+    }
+
+    // Per-pixel error of the best member against the full-size reference, using
+    // the current fitness metric where it has a per-pixel form (MSE otherwise)
+    pub fn best_error_map(&mut self) -> Vec<f64> {
+        self.members.sort();
+        self.reference.error_map(self.members.first().unwrap())
+    }
+
+    // `best_error_map` as reference-sized RGBA pixels, for drawing
+    pub fn best_error_heatmap(&mut self) -> Vec<u8> {
+        heatmap(&self.best_error_map())
+    }
+
+    // Mean of `best_error_map` over each of the best member's genes
+    pub fn best_gene_errors(&mut self) -> Vec<f64> {
+        let errors = self.best_error_map();
+        let best = self.members.first().unwrap();
+        best.gene_coverage_errors(&errors, self.reference.width, self.reference.height)
+    }
+
+    // Average self-adapted step size over every gene in the population, as a
+    // multiplier on the configured jitter. Watch this shrink as a run converges.
+    pub fn mean_step_scale(&self) -> f64 {
+        let (sum, count) = self
+            .members
+            .iter()
+            .flat_map(|m| m.genes.iter())
+            .fold((0.0, 0), |(sum, count), g| (sum + g.step_scale, count + 1));
+        if count == 0 {
+            1.0
+        } else {
+            sum / count as f64
+        }
+    }
+}
+
+impl Population {
+    pub fn with_config(
+        width: u32,
+        height: u32,
+        ref_values: Vec<u8>,
+        reference_w: u32,
+        reference_h: u32,
+        config: EvolutionConfig,
+    ) -> Result<Population, ConfigError> {
+        config.validate()?;
+        Ok(Self {
+            width,
+            height,
+            members: vec![],
+            reference: Reference::new(ref_values, reference_w, reference_h),
+            config,
+            rng: entropy_rng(),
+            level_best: f64::INFINITY,
+            stalled_generations: 0,
+        })
+    }
+
+    pub fn members(&self) -> &[RandomImage] {
+        &self.members
+    }
+
+    // Switches how fitness is scored and re-scores every member, since
+    // fitnesses from different metrics aren't comparable
+    pub fn set_metric(&mut self, metric: Box<dyn FitnessMetric>) {
+        self.reference.set_metric(metric);
+        self.level_best = f64::INFINITY;
+        self.stalled_generations = 0;
+        self.reevaluate();
+    }
+
+    // Per-pixel importance weights, one per reference pixel; None weights every
+    // pixel equally. Re-scores every member.
+    pub fn set_weights(&mut self, weights: Option<Vec<f64>>) -> Result<(), ConfigError> {
+        self.reference.set_weights(weights)?;
+        self.reevaluate();
+        Ok(())
+    }
+
+    // Moves to the next finer pyramid level once the best fitness at this one
+    // has stopped improving. Fitnesses at different levels aren't comparable,
+    // so everyone is re-scored.
+    fn update_pyramid_level(&mut self) {
+        if self.reference.level() == 0 || self.members.is_empty() {
+            return;
+        }
+        let best = self.best_fitness();
+        if best < self.level_best * (1.0 - self.config.pyramid_min_improvement) {
+            self.level_best = best;
+            self.stalled_generations = 0;
+            return;
+        }
+        self.stalled_generations += 1;
+        if self.stalled_generations >= self.config.pyramid_patience {
+            self.reference.promote();
+            self.reevaluate();
+            self.level_best = self.best_fitness();
+            self.stalled_generations = 0;
+        }
+    }
+
+    fn reevaluate(&mut self) {
+        for m in &mut self.members {
+            self.reference.evaluate(m);
+        }
+    }
+}
+
+// k-way tournament: sample `tournament_size` members (with replacement) and
+// return the index of the fittest one. Lower fitness is better.
+pub fn tournament_select(members: &[RandomImage], tournament_size: u32, rng: &mut Pcg32) -> usize {
+    let mut best = rng.gen_range(0, members.len());
+    for _ in 1..tournament_size {
+        let idx = rng.gen_range(0, members.len());
+        if members[idx] < members[best] {
+            best = idx;
+        }
+    }
+    best
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub enum CrossoverType {
+    Uniform,
+    OnePoint,
+    TwoPoint,
 }
 
 #[wasm_bindgen]
@@ -149,7 +511,26 @@ pub fn get_memory() -> JsValue {
     wasm_bindgen::memory()
 }
 
-#[derive(Clone, Debug)]
+/*
+How much of an image a point mutation touches:
+All - every parameter of every gene (the original behavior). The
+single-image optimizers, `HillClimber` and `SimulatedAnnealing`, take this as
+`Gene`.
+Gene - every parameter of one randomly chosen gene
+Component - one part of one gene (a vertex or other shape parameter, a color
+channel, the alpha, or a translation of the whole shape), picked using the
+`*_weight` config fields.
+Small, targeted changes like this are what allow fine convergence late in a run.
+*/
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub enum MutationMode {
+    All,
+    Gene,
+    Component,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
 struct Color {
     r: u8,
     g: u8,
@@ -173,7 +554,7 @@ impl Color {
         }
     }
 
-    fn random(rng: &mut rand::rngs::OsRng) -> Self {
+    fn random(rng: &mut Pcg32) -> Self {
         Self {
             r: rng.gen_range(0, 255),
             g: rng.gen_range(0, 255),
@@ -182,6 +563,26 @@ impl Color {
         }
     }
 
+    // This color over only `coverage` of a pixel, as a fainter color over all
+    // of it
+    fn with_coverage(&self, coverage: f64) -> Color {
+        Color {
+            a: (self.a as f64 * coverage).round() as u8,
+            ..self.clone()
+        }
+    }
+
+    // Part way from this color to `other`, channel by channel
+    fn lerp(&self, other: &Color, t: f64) -> Color {
+        let mix = |a: u8, b: u8| (a as f64 + t * (b as f64 - a as f64)).round() as u8;
+        Color {
+            r: mix(self.r, other.r),
+            g: mix(self.g, other.g),
+            b: mix(self.b, other.b),
+            a: mix(self.a, other.a),
+        }
+    }
+
     fn white() -> Self {
         Self {
             r: 255,
@@ -191,12 +592,44 @@ impl Color {
         }
     }
 
-    fn mutate(&mut self, rng: &mut rand::rngs::OsRng) {
-        let vary_amt = 5.0;
-        self.r = clamped_rand_range(self.r as f64, vary_amt, 0.0, 255.0, rng) as u8;
-        self.g = clamped_rand_range(self.g as f64, vary_amt, 0.0, 255.0, rng) as u8;
-        self.b = clamped_rand_range(self.b as f64, vary_amt, 0.0, 255.0, rng) as u8;
-        self.a = clamped_rand_range(self.a as f64, vary_amt, 0.0, 255.0, rng) as u8;
+    fn mutate(&mut self, config: &EvolutionConfig, rng: &mut Pcg32) {
+        for i in 0..4 {
+            self.jitter_channel(i, config, rng);
+        }
+    }
+
+    // Channels are indexed r, g, b, a
+    fn channel_mut(&mut self, i: usize) -> &mut u8 {
+        match i {
+            0 => &mut self.r,
+            1 => &mut self.g,
+            2 => &mut self.b,
+            _ => &mut self.a,
+        }
+    }
+
+    fn jitter_channel(&mut self, i: usize, config: &EvolutionConfig, rng: &mut Pcg32) {
+        let vary_amt = config.color_jitter;
+        let boundary = if i == 3 {
+            config.alpha_boundary
+        } else {
+            config.color_boundary
+        };
+        let distribution = config.mutation_distribution;
+        // Each channel value owns the unit interval around it, so the edge
+        // values are as likely to be stepped onto as the others, and a wrap
+        // goes from 255 to 0
+        let c = self.channel_mut(i);
+        let v = perturb(
+            *c as f64,
+            vary_amt,
+            -0.5,
+            255.5,
+            distribution,
+            boundary,
+            rng,
+        );
+        *c = clamp(v.round(), 0.0, 255.0) as u8;
     }
 
     // TODO: clean this up somehow
@@ -229,168 +662,390 @@ impl Color {
     }
 }
 
-struct Triangle {
-    v0: Point2<u32>,
-    v1: Point2<u32>,
-    v2: Point2<u32>,
-    bbox: BBox,
+/*
+How new genes are filled:
+Flat - with their color alone
+Linear - with a gradient between two points, from their color to another
+Radial - with a gradient out from a center, from their color to another
+Mixed - any of the three, picked uniformly for each gene
+*/
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub enum GradientKind {
+    Flat,
+    Linear,
+    Radial,
+    Mixed,
 }
 
-#[derive(Clone)]
-struct BBox {
-    origin: Point2<u32>,
-    extent: Point2<u32>,
-
-    // For iterator
-    _iter_x: u32,
-    _iter_y: u32,
+/*
+A gene's fill when it isn't flat. The gene's own color is the first stop and
+`color` the second; past either stop the fill stays at that stop's color.
+*/
+#[derive(Clone, PartialEq, Serialize)]
+enum Gradient {
+    // Changes along the line from `start` to `end`, constant across it
+    Linear {
+        start: Point2<f32>,
+        end: Point2<f32>,
+        color: Color,
+    },
+    // Changes with the distance from `center`, reaching `color` at `radius`
+    Radial {
+        center: Point2<f32>,
+        radius: f32,
+        color: Color,
+    },
 }
 
-impl BBox {
-    fn xmin(&self) -> u32 {
-        self.origin.x
-    }
-    fn xmax(&self) -> u32 {
-        self.extent.x
+impl Gradient {
+    // A gradient of `kind` laid out over `bbox` (the pixels of a shape on a
+    // `width` x `height` canvas), or none for a flat fill
+    fn random(
+        kind: GradientKind,
+        bbox: &BBox,
+        width: u32,
+        height: u32,
+        rng: &mut Pcg32,
+    ) -> Option<Gradient> {
+        let kind = match kind {
+            GradientKind::Mixed => match rng.gen_range(0, 3) {
+                0 => GradientKind::Flat,
+                1 => GradientKind::Linear,
+                _ => GradientKind::Radial,
+            },
+            kind => kind,
+        };
+        let (xmin, ymin) = (bbox.xmin(), bbox.ymin());
+        let (xmax, ymax) = (bbox.xmax().max(xmin + 1), bbox.ymax().max(ymin + 1));
+        let mut point = || {
+            let p = Point2::new(rng.gen_range(xmin, xmax), rng.gen_range(ymin, ymax));
+            pixel_position(&p, width, height)
+        };
+        match kind {
+            GradientKind::Linear => {
+                let (start, end) = (point(), point());
+                Some(Gradient::Linear {
+                    start,
+                    end,
+                    color: Color::random(rng),
+                })
+            }
+            GradientKind::Radial => {
+                let center = point();
+                let max = (xmax - xmin).max(ymax - ymin) as f64 / scale(width, height);
+                let radius = random_length(max, width, height, rng);
+                Some(Gradient::Radial {
+                    center,
+                    radius,
+                    color: Color::random(rng),
+                })
+            }
+            GradientKind::Flat | GradientKind::Mixed => None,
+        }
     }
-    fn ymax(&self) -> u32 {
-        self.extent.y
+
+    // Moves the anchors by (`dx`, `dy`), as positions, so the gradient stays
+    // put on a shape moved by as much
+    fn translate(&mut self, dx: f64, dy: f64) {
+        let shift = |p: &mut Point2<f32>| {
+            p.x = (p.x as f64 + dx) as f32;
+            p.y = (p.y as f64 + dy) as f32;
+        };
+        match self {
+            Gradient::Linear { start, end, .. } => {
+                shift(start);
+                shift(end);
+            }
+            Gradient::Radial { center, .. } => shift(center),
+        }
     }
-    fn new(v0: &Point2<u32>, v1: &Point2<u32>, v2: &Point2<u32>) -> BBox {
-        let xs = vec![v0.x, v1.x, v2.x];
-        let ys = vec![v0.y, v1.y, v2.y];
-        let &min_x = xs.iter().min().unwrap();
-        let &min_y = ys.iter().min().unwrap();
-        let &max_x = xs.iter().max().unwrap();
-        let &max_y = ys.iter().max().unwrap();
 
-        BBox {
-            origin: Point2::new(min_x, min_y),
-            extent: Point2::new(max_x, max_y),
-            _iter_x: min_x,
-            _iter_y: min_y,
+    // The second stop's color
+    fn color_mut(&mut self) -> &mut Color {
+        match self {
+            Gradient::Linear { color, .. } | Gradient::Radial { color, .. } => color,
         }
     }
-}
 
-impl Iterator for BBox {
-    type Item = Point2<u32>;
+    // The start and end, or the center and radius
+    fn parts(&self) -> usize {
+        2
+    }
 
-    // Here, we define the sequence using `.curr` and `.next`.
-    // The return type is `Option<T>`:
-    //     * When the `Iterator` is finished, `None` is returned.
-    //     * Otherwise, the next value is wrapped in `Some` and returned.
-    fn next(&mut self) -> Option<Point2<u32>> {
-        let cur = Point2::new(self._iter_x, self._iter_y);
-        self._iter_x += 1;
-        if self._iter_x >= self.xmax() {
-            self._iter_x = self.xmin();
-            self._iter_y += 1;
+    fn jitter_part(
+        &mut self,
+        i: usize,
+        width: u32,
+        height: u32,
+        config: &EvolutionConfig,
+        rng: &mut Pcg32,
+    ) {
+        match (self, i) {
+            (Gradient::Linear { start, .. }, 0) => jitter_point(start, config, rng),
+            (Gradient::Linear { end, .. }, _) => jitter_point(end, config, rng),
+            (Gradient::Radial { center, .. }, 0) => jitter_point(center, config, rng),
+            (Gradient::Radial { radius, .. }, _) => {
+                jitter_length(radius, width, height, config, rng)
+            }
         }
-        if self._iter_y >= self.ymax() {
-            return None;
+    }
+
+    fn mutate(&mut self, width: u32, height: u32, config: &EvolutionConfig, rng: &mut Pcg32) {
+        for i in 0..self.parts() {
+            self.jitter_part(i, width, height, config, rng);
         }
-        Some(cur)
+        self.color_mut().mutate(config, rng);
+    }
+
+    // The fill at pixel `p` of a `width` x `height` canvas, given the gene's
+    // own color as the first stop
+    fn color_at(&self, first: &Color, p: &Point2<u32>, width: u32, height: u32) -> Color {
+        let (px, py) = pixel_center(p);
+        let (t, second) = match self {
+            Gradient::Linear { start, end, color } => {
+                let (sx, sy) = to_pixels(start, width, height);
+                let (ex, ey) = to_pixels(end, width, height);
+                let (dx, dy) = (ex - sx, ey - sy);
+                let length_squared = dx * dx + dy * dy;
+                let t = if length_squared > 0.0 {
+                    ((px - sx) * dx + (py - sy) * dy) / length_squared
+                } else {
+                    0.0
+                };
+                (t, color)
+            }
+            Gradient::Radial {
+                center,
+                radius,
+                color,
+            } => {
+                let (cx, cy) = to_pixels(center, width, height);
+                let (dx, dy) = (px - cx, py - cy);
+                let radius = *radius as f64 * scale(width, height);
+                ((dx * dx + dy * dy).sqrt() / radius, color)
+            }
+        };
+        first.lerp(second, clamp(t, 0.0, 1.0))
     }
 }
 
-impl Triangle {
-    pub fn new(v0x: u32, v0y: u32, v1x: u32, v1y: u32, v2x: u32, v2y: u32) -> Triangle {
-        let v0 = Point2::new(v0x, v0y);
-        let v1 = Point2::new(v1x, v1y);
-        let v2 = Point2::new(v2x, v2y);
-        let bbox = BBox::new(&v0, &v1, &v2);
-        Triangle { v0, v1, v2, bbox }
+/*
+A shape, a color, the gene's own step size (a multiplier on the configured
+jitter that evolves along with the gene, see `adapt_step_scale`) and,
+optionally, a gradient that the color is the first stop of.
+*/
+#[derive(Clone, PartialEq, Serialize)]
+struct Gene {
+    geometry: Geometry,
+    color: Color,
+    step_scale: f64,
+    gradient: Option<Gradient>,
+}
+impl Gene {
+    fn random(config: &EvolutionConfig, width: u32, height: u32, rng: &mut Pcg32) -> Gene {
+        let geometry = Geometry::random(config, width, height, rng);
+        let color = Color::random(rng);
+        let bbox = geometry.shape().bbox(width, height);
+        let gradient = Gradient::random(config.gradient_kind, &bbox, width, height, rng);
+        Gene {
+            geometry,
+            color,
+            step_scale: 1.0,
+            gradient,
+        }
     }
 
     /*
-    Cross product
-        [
-        a[1] * b[2] - a[2] * b[1],
-        a[2] * b[0] - a[0] * b[2],
-        a[0] * b[1] - a[1] * b[0]
-    ]
+    A random gene within about a quarter of the canvas of a point drawn from
+    `guide`, so it covers the area around that point.
     */
-    fn barycentric(&self, p: &Point2<u32>) -> Option<Vector3<f64>> {
-        let v1 = Vector3::new(
-            self.v2.x as f64 - self.v0.x as f64,
-            self.v1.x as f64 - self.v0.x as f64,
-            self.v0.x as f64 - p.x as f64,
-        );
-        let v2 = Vector3::new(
-            self.v2.y as f64 - self.v0.y as f64,
-            self.v1.y as f64 - self.v0.y as f64,
-            self.v0.y as f64 - p.y as f64,
-        );
-        let u = Vector3::new(
-            v1.y * v2.z - v1.z * v2.y,
-            v1.z * v2.x - v1.x * v2.z,
-            v1.x * v2.y - v1.y * v2.x,
-        );
-        if u.z.abs() < 1.0 {
-            None
-        } else {
-            Some(Vector3::new(1.0 - (u.x + u.y) / u.z, u.y / u.z, u.x / u.z))
+    fn random_near(
+        config: &EvolutionConfig,
+        guide: &ErrorGuide,
+        width: u32,
+        height: u32,
+        rng: &mut Pcg32,
+    ) -> Gene {
+        let center = pixel_position(&guide.sample_point(width, height, rng), width, height);
+        let spread = (0.25, 0.25);
+        let geometry = Geometry::random_near(config, center, spread, width, height, rng);
+        let color = Color::random(rng);
+        let bbox = geometry.shape().bbox(width, height);
+        let gradient = Gradient::random(config.gradient_kind, &bbox, width, height, rng);
+        Gene {
+            geometry,
+            color,
+            step_scale: 1.0,
+            gradient,
         }
     }
 
-    fn contains(&self, p: &Point2<u32>) -> bool {
-        match self.barycentric(p) {
-            Some(v3) => v3.x > 0.0 && v3.y > 0.0 && v3.z > 0.0,
-            _ => false,
+    // The shape's parts, then the gradient's
+    fn parts(&self, config: &EvolutionConfig) -> usize {
+        let gradient = self.gradient.as_ref().map_or(0, |g| g.parts());
+        self.geometry.shape().parts(config) + gradient
+    }
+
+    // Either color stop, at random, if there are two
+    fn stop_mut(&mut self, rng: &mut Pcg32) -> &mut Color {
+        let second = self.gradient.is_some() && rng.gen::<bool>();
+        match self.gradient {
+            Some(ref mut gradient) if second => gradient.color_mut(),
+            _ => &mut self.color,
         }
     }
-}
 
-#[derive(Clone)]
-struct Gene(Point2<u32>, Point2<u32>, Point2<u32>, Color);
-impl Gene {
-    fn random(width: u32, height: u32, rng: &mut rand::rngs::OsRng) -> Gene {
-        Gene(
-            Point2::new(rng.gen_range(0, width), rng.gen_range(0, height)),
-            Point2::new(rng.gen_range(0, width), rng.gen_range(0, height)),
-            Point2::new(rng.gen_range(0, width), rng.gen_range(0, height)),
-            Color::random(rng),
-        )
+    fn color_at(&self, p: &Point2<u32>, width: u32, height: u32) -> Color {
+        match self.gradient {
+            Some(ref gradient) => gradient.color_at(&self.color, p, width, height),
+            None => self.color.clone(),
+        }
     }
-    fn mutate(&mut self, width: u32, height: u32, rng: &mut rand::rngs::OsRng) {
-        let mutate_w = 0.05 * width as f64;
-        let mutate_h = 0.05 * height as f64;
 
-        fn clamp(v: f64, max: u32) -> u32 {
-            if v < 0.0 {
-                0
-            } else if v as u32 >= max {
-                max - 1
-            } else {
-                v as u32
-            }
+    /*
+    ES-style self-adaptation: when enabled, the step size is itself mutated
+    (log-normally, so it is equally likely to halve as to double) before being
+    used. Genes whose step size suits the current stage of the run produce
+    better offspring, so good step sizes get selected along with good genes.
+    Returns the config with the jitter amounts scaled by this gene's step size.
+    */
+    fn adapt_step_scale(&mut self, config: &EvolutionConfig, rng: &mut Pcg32) -> EvolutionConfig {
+        if config.self_adaptive {
+            // Learning rate for a single step size over the gene's 10 parameters
+            let tau = 1.0 / (10.0f64).sqrt();
+            let n: f64 = rng.sample(StandardNormal);
+            self.step_scale = clamp(
+                self.step_scale * (tau * n).exp(),
+                config.min_step_scale,
+                config.max_step_scale,
+            );
         }
+        config.with_step_scale(self.step_scale)
+    }
+
+    fn mutate(&mut self, width: u32, height: u32, config: &EvolutionConfig, rng: &mut Pcg32) {
+        let config = &self.adapt_step_scale(config, rng);
+        self.geometry.shape_mut().mutate(width, height, config, rng);
+        self.color.mutate(config, rng);
+        if let Some(ref mut gradient) = self.gradient {
+            gradient.mutate(width, height, config, rng);
+        }
+    }
 
-        fn clamped_rand(v: u32, rnd_width: f64, max: u32, rng: &mut rand::rngs::OsRng) -> u32 {
-            let v = v as f64;
-            let lo = clamp((v - rnd_width / 2.0) as f64, max);
-            let hi = clamp((v + rnd_width / 2.0) as f64, max);
-            rng.gen_range(lo, hi) as u32
+    fn mutate_component(
+        &mut self,
+        component: GeneComponent,
+        width: u32,
+        height: u32,
+        config: &EvolutionConfig,
+        rng: &mut Pcg32,
+    ) {
+        let config = &self.adapt_step_scale(config, rng);
+        let parts = self.geometry.shape().parts(config);
+        match component {
+            GeneComponent::Vertex(i) if i < parts => self
+                .geometry
+                .shape_mut()
+                .jitter_part(i, width, height, config, rng),
+            GeneComponent::Vertex(i) => {
+                if let Some(ref mut gradient) = self.gradient {
+                    gradient.jitter_part(i - parts, width, height, config, rng);
+                }
+            }
+            GeneComponent::Channel(i) => self.stop_mut(rng).jitter_channel(i, config, rng),
+            GeneComponent::Alpha => self.stop_mut(rng).jitter_channel(3, config, rng),
+            GeneComponent::Translate => {
+                let (x, y) = self.geometry.shape().center();
+                self.geometry
+                    .shape_mut()
+                    .translate(width, height, config, rng);
+                if let Some(ref mut gradient) = self.gradient {
+                    let (moved_x, moved_y) = self.geometry.shape().center();
+                    gradient.translate(moved_x - x, moved_y - y);
+                }
+            }
         }
+    }
+}
 
-        self.0.x = clamped_rand(self.0.x, mutate_w, width, rng);
-        self.0.y = clamped_rand(self.0.y, mutate_h, height, rng);
-        self.1.x = clamped_rand(self.1.x, mutate_w, width, rng);
-        self.1.y = clamped_rand(self.1.y, mutate_h, height, rng);
-        self.2.x = clamped_rand(self.2.x, mutate_w, width, rng);
-        self.2.y = clamped_rand(self.2.y, mutate_h, height, rng);
-        self.3.mutate(rng); // color
+// Which single part of a gene `MutationMode::Component` changes. `Vertex` is
+// one of the shape's parts: a vertex of a triangle, or e.g. the center, a
+// radius or the angle of an ellipse; or one of the gradient's, if it has one.
+// `Channel` and `Alpha` pick either of a gradient's color stops.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum GeneComponent {
+    Vertex(usize),
+    Channel(usize),
+    Alpha,
+    Translate,
+}
+
+impl GeneComponent {
+    // Picks a component type by its configured weight, then one of the
+    // shape's `parts` or a channel uniformly within that type
+    fn random(config: &EvolutionConfig, parts: usize, rng: &mut Pcg32) -> GeneComponent {
+        let weights = [
+            config.vertex_weight,
+            config.channel_weight,
+            config.alpha_weight,
+            config.translate_weight,
+        ];
+        let mut pick = rng.gen::<f64>() * weights.iter().sum::<f64>();
+        let mut kind = weights.len() - 1;
+        for (i, w) in weights.iter().enumerate() {
+            if pick < *w {
+                kind = i;
+                break;
+            }
+            pick -= w;
+        }
+        match kind {
+            0 => GeneComponent::Vertex(rng.gen_range(0, parts)),
+            1 => GeneComponent::Channel(rng.gen_range(0, 3)),
+            2 => GeneComponent::Alpha,
+            _ => GeneComponent::Translate,
+        }
     }
 }
 
+/*
+What `genome_json` writes: everything needed to redraw an image. Version 1 was
+a bare array of genes; version 2 wraps them in an object alongside the
+background color, which is no longer always white; version 3 writes each gene
+as an object with named fields rather than an array.
+*/
+const GENOME_VERSION: u32 = 3;
+
+#[derive(Serialize)]
+struct Genome<'a> {
+    version: u32,
+    background: &'a Color,
+    genes: &'a [Gene],
+}
+
+fn genome_json(background: &Color, genes: &[Gene]) -> String {
+    serde_json::to_string(&Genome {
+        version: GENOME_VERSION,
+        background,
+        genes,
+    })
+    .unwrap()
+}
+
 #[wasm_bindgen]
+#[derive(Clone)]
 pub struct RandomImage {
     width: u32,
     height: u32,
     pixels: Vec<Pixel>,
     genes: Vec<Gene>,
+    // What the shapes are drawn over
+    background: Color,
     fitness: f64,
+    // Per-pixel errors from its last `reevaluate_changes`, empty when unknown
+    // (see `Reference::evaluate_region`)
+    errors: Vec<f64>,
 }
 
 impl Ord for RandomImage {
@@ -422,73 +1077,68 @@ impl Eq for RandomImage {}
 #[wasm_bindgen]
 impl RandomImage {
     pub fn new(width: u32, height: u32) -> RandomImage {
-        let size = (width * height) as usize;
-        let white = Color::white();
-        let pixels: Vec<Pixel> = (0..size).map(|_| Pixel::of_color(&white)).collect();
-
-        let gene_count = 50;
-        let mut genes = vec![];
-        let mut rng = OsRng::new().unwrap();
-        for _ in 0..gene_count {
-            genes.push(Gene::random(width, height, &mut rng));
-        }
-        RandomImage {
-            width,
-            height,
-            pixels,
-            genes,
-            fitness: 0.0,
-        }
+        RandomImage::new_with_config(width, height, &EvolutionConfig::default())
     }
 
     pub fn new_with_gene_count(width: u32, height: u32, gene_count: u32) -> RandomImage {
-        let size = (width * height) as usize;
-        let white = Color::white();
-        let pixels: Vec<Pixel> = (0..size).map(|_| Pixel::of_color(&white)).collect();
+        let config = EvolutionConfig {
+            gene_count,
+            ..EvolutionConfig::default()
+        };
+        RandomImage::new_with_config(width, height, &config)
+    }
 
-        let mut genes = vec![];
-        let mut rng = OsRng::new().unwrap();
-        for _ in 0..gene_count {
-            genes.push(Gene::random(width, height, &mut rng));
-        }
-        RandomImage {
-            width,
-            height,
-            pixels,
-            genes,
-            fitness: 0.0,
-        }
+    pub fn new_with_config(width: u32, height: u32, config: &EvolutionConfig) -> RandomImage {
+        RandomImage::new_with_rng(width, height, config, &mut entropy_rng())
     }
 
     pub fn render(&mut self) {
         for pixel in &mut self.pixels {
-            pixel.set_color(&Color::white());
+            pixel.set_color(&self.background);
         }
-        for gene in self.genes.clone() {
-            let color = gene.3;
-            self.triangle(
-                gene.0.x, gene.0.y, gene.1.x, gene.1.y, gene.2.x, gene.2.y, &color,
-            );
+        for i in 0..self.genes.len() {
+            self.draw_gene(i, None);
         }
     }
 
-    // TODO -- figure out how to use this!
-    pub fn breed(&mut self, other: &RandomImage) {
-        let mut rng = OsRng::new().unwrap();
-        let rel_fitness = self.fitness / (self.fitness + other.fitness);
-        for (lhs, rhs) in self.genes.iter_mut().zip(&other.genes) {
-            if rng.gen::<f64>() > rel_fitness {
-                *lhs = rhs.clone();
-            }
-        }
+    /*
+    A copy of this image drawn on a `width` x `height` canvas. Gene geometry is
+    stored relative to the canvas, so an image evolved small can be rendered at
+    print size. The copy isn't scored.
+    */
+    pub fn render_at(&self, width: u32, height: u32) -> RandomImage {
+        let background = self.background.clone();
+        let mut image = RandomImage {
+            width,
+            height,
+            pixels: (0..width * height)
+                .map(|_| Pixel::of_color(&background))
+                .collect(),
+            genes: self.genes.clone(),
+            background,
+            fitness: 0.0,
+            errors: vec![],
+        };
+        image.render();
+        image
+    }
+
+    /*
+    Crosses `other`'s genes into this image.
+    Uniform crossover takes each gene from `other` with a probability weighted by
+    relative fitness (lower is better, so a worse `self` gives up more genes).
+    One- and two-point crossover take the tail, or a middle segment, from `other`.
+    */
+    pub fn breed(&mut self, other: &RandomImage, crossover: CrossoverType) {
+        self.breed_with_rng(other, crossover, &mut entropy_rng());
     }
 
-    pub fn mutate(&mut self) {
-        let mut rng = OsRng::new().unwrap();
+    pub fn mutate(&mut self, config: &EvolutionConfig) {
+        self.mutate_with_rng(config, &mut entropy_rng());
+    }
 
-        for gene in &mut self.genes {
-            gene.mutate(self.width, self.height, &mut rng);
-        }
+    pub fn genome_json(&self) -> String {
+        genome_json(&self.background, &self.genes)
     }
 
     pub fn width(&self) -> u32 {
@@ -499,6 +1149,10 @@ impl RandomImage {
         self.height
     }
 
+    pub fn fitness(&self) -> f64 {
+        self.fitness
+    }
+
     pub fn size(&self) -> usize {
         (self.width * self.height) as usize
     }
@@ -525,23 +1179,23 @@ impl RandomImage {
         idx
     }
 
-    fn triangle(
-        &mut self,
-        v0x: u32,
-        v0y: u32,
-        v1x: u32,
-        v1y: u32,
-        v2x: u32,
-        v2y: u32,
-        color: &Color,
-    ) {
-        let t = Triangle::new(v0x, v0y, v1x, v1y, v2x, v2y);
-        for p in t.bbox.clone() {
-            if t.contains(&p) {
-                let idx = self.pixel_index(p.x, p.y);
-                self.pixels[idx].add_color(&color);
-            }
-        }
+    // Blends gene `index` into the pixels its shape covers, or only those
+    // inside `clip`
+    fn draw_gene(&mut self, index: usize, clip: Option<&BBox>) {
+        let (width, height) = (self.width, self.height);
+        let gene = &self.genes[index];
+        let pixels = &mut self.pixels;
+        gene.geometry
+            .shape()
+            .rasterize_coverage(width, height, clip, &mut |p, coverage| {
+                let pixel = &mut pixels[(p.y * width + p.x) as usize];
+                let color = gene.color_at(&p, width, height);
+                if coverage < 1.0 {
+                    pixel.add_color(&color.with_coverage(coverage));
+                } else {
+                    pixel.add_color(&color);
+                }
+            });
     }
 
     pub fn shrink(&self, width: u32, height: u32) -> RandomImage {
@@ -568,30 +1222,14 @@ impl RandomImage {
             height: self.height / tile_height,
             pixels,
             genes: vec![],
+            background: Color::white(),
             fitness: 0.0,
+            errors: vec![],
         };
 
         for tile_row in 0..shrunk_img.height {
             for tile_col in 0..shrunk_img.width {
-                let mut sum_r: u32 = 0;
-                let mut sum_g: u32 = 0;
-                let mut sum_b: u32 = 0;
-                for x in (tile_col * tile_width)..((tile_col + 1) * tile_width) {
-                    for y in (tile_row * tile_height)..((tile_row + 1) * tile_height) {
-                        let pixel = self.get_pixel(x, y); // <-- This must copy the pixel, so it may be a source of slowdown,
-                                                          // but the pixel data structure should be fast to copy since it is just 4 u8s
-                        sum_r += pixel.r as u32;
-                        sum_g += pixel.g as u32;
-                        sum_b += pixel.b as u32;
-                    }
-                }
-
-                let avg_pixel = Pixel {
-                    r: (sum_r / size as u32) as u8,
-                    g: (sum_g / size as u32) as u8,
-                    b: (sum_b / size as u32) as u8,
-                    a: 255,
-                };
+                let avg_pixel = self.tile_average(tile_col, tile_row, tile_width, tile_height);
                 shrunk_img.pixels.push(avg_pixel);
             }
         }
@@ -616,25 +1254,60 @@ impl RandomImage {
         self.compare_values(values)
     }
 
+    // Mean squared error against RGBA `values`, 4 per pixel
     pub fn compare_values(&self, values: &[u8]) -> f64 {
-        let mut err = 0.0;
-        if self.pixels.len() * 4 != values.len() {
-            log!("Got bad sizes for compare");
-            panic!("Got bad sizes for compare");
-        }
+        let metric = Mse {
+            ignore_alpha: false,
+        };
+        metric.error(&self.pixels, values, self.width, self.height)
+    }
 
-        fn squared_error(lhs: u8, rhs: u8) -> f64 {
-            (lhs as f64 - rhs as f64).powi(2)
-        }
+    // Squared error of each pixel (averaged over channels) of this image,
+    // rendered and shrunk to `reference_w` x `reference_h`, against the reference
+    pub fn error_map(
+        &mut self,
+        reference_values: &[u8],
+        reference_w: u32,
+        reference_h: u32,
+    ) -> Vec<f64> {
+        self.render();
+        Reference::new(reference_values.to_vec(), reference_w, reference_h).error_map(self)
+    }
 
-        for (idx, pixel) in self.pixels.iter().enumerate() {
-            err += squared_error(pixel.r, values[idx]);
-            err += squared_error(pixel.g, values[idx + 1]);
-            err += squared_error(pixel.b, values[idx + 2]);
-            err += squared_error(pixel.a, values[idx + 3]);
-        }
+    // `error_map` as RGBA pixels, black (no error) through red and yellow to
+    // white (the worst pixel), e.g. for drawing into a canvas
+    pub fn error_heatmap(
+        &mut self,
+        reference_values: &[u8],
+        reference_w: u32,
+        reference_h: u32,
+    ) -> Vec<u8> {
+        heatmap(&self.error_map(reference_values, reference_w, reference_h))
+    }
 
-        err / values.len() as f64
+    // For each gene, the mean of `error_map` over the area its shape covers
+    pub fn gene_errors(
+        &mut self,
+        reference_values: &[u8],
+        reference_w: u32,
+        reference_h: u32,
+    ) -> Vec<f64> {
+        let errors = self.error_map(reference_values, reference_w, reference_h);
+        self.gene_coverage_errors(&errors, reference_w, reference_h)
+    }
+
+    // Sets gene `index`'s RGB to whatever minimizes the squared error against the
+    // reference, keeping its shape and alpha, and re-renders. See `solve_gene_color`.
+    pub fn optimize_gene_color(
+        &mut self,
+        index: usize,
+        reference_values: &[u8],
+        reference_w: u32,
+        reference_h: u32,
+    ) {
+        let reference = Reference::new(reference_values.to_vec(), reference_w, reference_h);
+        self.render();
+        self.solve_gene_color(index, &reference);
     }
 
     // TODO - Is this a speed/memory issue, that the pixel is copied? It is a lightweight struct so
@@ -644,8 +1317,611 @@ impl RandomImage {
     }
 }
 
+impl RandomImage {
+    // The average color of one `tile_width` x `tile_height` tile, which
+    // becomes pixel (`tile_col`, `tile_row`) when shrinking by tiles
+    fn tile_average(
+        &self,
+        tile_col: u32,
+        tile_row: u32,
+        tile_width: u32,
+        tile_height: u32,
+    ) -> Pixel {
+        let size = tile_width * tile_height;
+        let mut sum_r: u32 = 0;
+        let mut sum_g: u32 = 0;
+        let mut sum_b: u32 = 0;
+        for x in (tile_col * tile_width)..((tile_col + 1) * tile_width) {
+            for y in (tile_row * tile_height)..((tile_row + 1) * tile_height) {
+                let pixel = self.get_pixel(x, y); // <-- This must copy the pixel, so it may be a source of slowdown,
+                                                  // but the pixel data structure should be fast to copy since it is just 4 u8s
+                sum_r += pixel.r as u32;
+                sum_g += pixel.g as u32;
+                sum_b += pixel.b as u32;
+            }
+        }
+
+        Pixel {
+            r: (sum_r / size) as u8,
+            g: (sum_g / size) as u8,
+            b: (sum_b / size) as u8,
+            a: 255,
+        }
+    }
+
+    /*
+    Redraws only the pixels in `region`: they're cleared to the background and
+    every gene whose bounding box reaches into the region is blended in again,
+    in order, so they end up exactly as `render` would leave them.
+    */
+    fn render_region(&mut self, region: &BBox) {
+        let x_end = region.xmax().min(self.width);
+        let y_end = region.ymax().min(self.height);
+        for y in region.ymin()..y_end {
+            for x in region.xmin()..x_end {
+                let idx = self.pixel_index(x, y);
+                self.pixels[idx].set_color(&self.background);
+            }
+        }
+        let (width, height) = (self.width, self.height);
+        for i in 0..self.genes.len() {
+            if self.genes[i]
+                .geometry
+                .shape()
+                .bbox(width, height)
+                .intersects(region)
+            {
+                self.draw_gene(i, Some(region));
+            }
+        }
+    }
+
+    /*
+    The part of the canvas that may look different from when it was rendered
+    from `genes` over `background`: the bounding boxes of the genes that changed,
+    which are the ones left after skipping the genes both lists start and end
+    with, old and new together. A one-gene mutation is just that gene's old and
+    new boxes, an insertion or removal just the one gene.
+    None when the whole canvas should be redrawn: the background changed, or
+    nothing did.
+    */
+    fn changed_region(&self, genes: &[Gene], background: &Color) -> Option<BBox> {
+        if self.background != *background {
+            return None;
+        }
+        let prefix = genes
+            .iter()
+            .zip(self.genes.iter())
+            .take_while(|(old, new)| old == new)
+            .count();
+        let suffix = genes[prefix..]
+            .iter()
+            .rev()
+            .zip(self.genes[prefix..].iter().rev())
+            .take_while(|(old, new)| old == new)
+            .count();
+        genes[prefix..genes.len() - suffix]
+            .iter()
+            .chain(self.genes[prefix..self.genes.len() - suffix].iter())
+            .map(|gene| gene.geometry.shape().bbox(self.width, self.height))
+            .fold(None, |region: Option<BBox>, bbox| {
+                Some(match region {
+                    Some(region) => region.union(&bbox),
+                    None => bbox,
+                })
+            })
+    }
+
+    /*
+    Re-scores this image after it changed from `genes` over `background`,
+    redrawing and re-scoring only the `changed_region`. It must have been
+    evaluated by `reference` before the change; if nothing changed, that score
+    stands.
+    */
+    fn reevaluate_changes(&mut self, reference: &Reference, genes: &[Gene], background: &Color) {
+        if self.genes[..] == *genes && self.background == *background {
+            return;
+        }
+        let region = self.changed_region(genes, background);
+        let mut errors = vec![];
+        mem::swap(&mut errors, &mut self.errors);
+        reference.evaluate_region(self, region.as_ref(), &mut errors);
+        self.errors = errors;
+    }
+
+    /*
+    Averages per-pixel `errors`, for this image shrunk to `width` x `height`,
+    over the pixels each gene's shape covers. Coverage is counted at full
+    size, so a gene covering part of a shrunk pixel counts it partly.
+    Genes that cover nothing get 0.
+    */
+    pub fn gene_coverage_errors(&self, errors: &[f64], width: u32, height: u32) -> Vec<f64> {
+        // An error map finer than the canvas is read one canvas pixel per
+        // error pixel
+        let tile_width = (self.width / width).max(1);
+        let tile_height = (self.height / height).max(1);
+
+        self.genes
+            .iter()
+            .map(|gene| {
+                let (mut total, mut count) = (0.0, 0);
+                gene.geometry
+                    .shape()
+                    .rasterize(self.width, self.height, None, &mut |p| {
+                        let (x, y) = (p.x / tile_width, p.y / tile_height);
+                        if x < width && y < height {
+                            total += errors[(y * width + x) as usize];
+                            count += 1;
+                        }
+                    });
+                if count > 0 {
+                    total / count as f64
+                } else {
+                    0.0
+                }
+            })
+            .collect()
+    }
+
+    /*
+    Closed-form best color for one gene. Everything is drawn "over" an opaque
+    background, so each canvas pixel the gene covers ends up as
+        alpha * T * color + K
+    where alpha is the gene's, scaled down where it only partly covers the
+    pixel, T is how much of it shows through the genes drawn above and K is what
+    the pixels beneath and the genes above contribute. Shrinking averages those
+    into tiles, so each shrunk pixel is also linear in the color, and least
+    squares against the reference gives, per channel,
+        color = sum(a * (reference - b)) / sum(a * a)
+    for each shrunk pixel's coefficient a and constant b.
+    Rounding in the renderer makes the result very nearly, rather than
+    exactly, optimal.
+    Only the tiles under the gene are redrawn, so pixels elsewhere are left as
+    current as they were.
+    */
+    pub fn solve_gene_color(&mut self, index: usize, reference: &Reference) {
+        // A gradient's color varies over the shape, so there's no one color to
+        // solve for
+        if index >= self.genes.len() || self.genes[index].gradient.is_some() {
+            return;
+        }
+        let (width, height) = (self.width, self.height);
+        let tile_width = (width / reference.width).max(1);
+        let tile_height = (height / reference.height).max(1);
+        let (shrunk_width, shrunk_height) = (width / tile_width, height / tile_height);
+        let tile_size = (tile_width * tile_height) as f64;
+
+        // Only the whole tiles the gene reaches can change, so only they are
+        // drawn: first with just what's beneath the gene, then in full
+        let bbox = self.genes[index].geometry.shape().bbox(width, height);
+        let (x0, y0) = (
+            bbox.xmin() / tile_width * tile_width,
+            bbox.ymin() / tile_height * tile_height,
+        );
+        let x1 = (((bbox.xmax() - 1) / tile_width + 1) * tile_width).min(width) - 1;
+        let y1 = (((bbox.ymax() - 1) / tile_height + 1) * tile_height).min(height) - 1;
+        let region = BBox::spanning(x0, y0, x1, y1);
+        let region_width = x1 - x0 + 1;
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                let i = self.pixel_index(x, y);
+                self.pixels[i].set_color(&self.background);
+            }
+        }
+        for i in 0..index {
+            if self.genes[i]
+                .geometry
+                .shape()
+                .bbox(width, height)
+                .intersects(&region)
+            {
+                self.draw_gene(i, Some(&region));
+            }
+        }
+        let mut beneath = Vec::with_capacity((region_width * (y1 - y0 + 1)) as usize);
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                beneath.push(self.pixels[self.pixel_index(x, y)]);
+            }
+        }
+        self.render_region(&region);
+
+        // For each covered pixel: [a * T, K_r, K_g, K_b], with a the gene's
+        // alpha scaled by how much of the pixel it covers, as `draw_gene`
+        // blends it, and K so far just the part of what's beneath that shows
+        // through this gene
+        let color = self.genes[index].color.clone();
+        let mut covered: BTreeMap<usize, [f64; 4]> = BTreeMap::new();
+        self.genes[index].geometry.shape().rasterize_coverage(
+            width,
+            height,
+            Some(&region),
+            &mut |p, coverage| {
+                let a = color.with_coverage(coverage).a as f64 / 255.0;
+                let b = &beneath[((p.y - y0) * region_width + p.x - x0) as usize];
+                let k = |v: u8| (1.0 - a) * v as f64;
+                covered.insert((p.y * width + p.x) as usize, [a, k(b.r), k(b.g), k(b.b)]);
+            },
+        );
+        for above in self.genes[index + 1..].iter() {
+            above.geometry.shape().rasterize_coverage(
+                width,
+                height,
+                Some(&region),
+                &mut |p, coverage| {
+                    if let Some(v) = covered.get_mut(&((p.y * width + p.x) as usize)) {
+                        let fill = above.color_at(&p, width, height).with_coverage(coverage);
+                        let a = fill.a as f64 / 255.0;
+                        let color = [fill.r, fill.g, fill.b];
+                        v[0] *= 1.0 - a;
+                        for c in 0..3 {
+                            v[c + 1] = a * color[c] as f64 + (1.0 - a) * v[c + 1];
+                        }
+                    }
+                },
+            );
+        }
+
+        // Per touched shrunk pixel: [a, b_r, b_g, b_b]
+        let mut tiles: BTreeMap<usize, [f64; 4]> = BTreeMap::new();
+        for (&i, v) in covered.iter() {
+            let (x, y) = (
+                i as u32 % width / tile_width,
+                i as u32 / width / tile_height,
+            );
+            if x < shrunk_width && y < shrunk_height {
+                let tile = tiles
+                    .entry((y * shrunk_width + x) as usize)
+                    .or_insert([0.0; 4]);
+                tile[0] += v[0] / tile_size;
+                for c in 0..3 {
+                    tile[c + 1] += v[c + 1] / tile_size;
+                }
+            }
+        }
+        // Uncovered pixels in those tiles don't depend on the color
+        for (&q, tile) in tiles.iter_mut() {
+            let (x, y) = (q as u32 % shrunk_width, q as u32 / shrunk_width);
+            for py in y * tile_height..(y + 1) * tile_height {
+                for px in x * tile_width..(x + 1) * tile_width {
+                    let i = self.pixel_index(px, py);
+                    if !covered.contains_key(&i) {
+                        let p = &self.pixels[i];
+                        tile[1] += p.r as f64 / tile_size;
+                        tile[2] += p.g as f64 / tile_size;
+                        tile[3] += p.b as f64 / tile_size;
+                    }
+                }
+            }
+        }
+
+        let mut numerator = [0.0; 3];
+        let mut denominator = 0.0;
+        for (&q, tile) in tiles.iter() {
+            let target = &reference.values[q * 4..q * 4 + 3];
+            for c in 0..3 {
+                numerator[c] += tile[0] * (target[c] as f64 - tile[c + 1]);
+            }
+            denominator += tile[0] * tile[0];
+        }
+        if denominator > 0.0 {
+            let solve = |c: usize| clamp(numerator[c] / denominator, 0.0, 255.0).round() as u8;
+            let (r, g, b) = (solve(0), solve(1), solve(2));
+            let color = &mut self.genes[index].color;
+            color.r = r;
+            color.g = g;
+            color.b = b;
+        }
+        self.render_region(&region);
+    }
+
+    // The "color-solve" mutation: gives one gene (picked by `guide` if there is
+    // one) its optimal color
+    pub fn color_solve_step(
+        &mut self,
+        reference: &Reference,
+        guide: Option<&ErrorGuide>,
+        rng: &mut Pcg32,
+    ) {
+        if self.genes.is_empty() {
+            return;
+        }
+        let index = self.pick_gene(guide, rng);
+        self.solve_gene_color(index, reference);
+    }
+
+    pub fn new_with_rng(
+        width: u32,
+        height: u32,
+        config: &EvolutionConfig,
+        rng: &mut Pcg32,
+    ) -> RandomImage {
+        let size = (width * height) as usize;
+        let background = initial_background(None, config);
+        let pixels: Vec<Pixel> = (0..size).map(|_| Pixel::of_color(&background)).collect();
+
+        let mut genes = vec![];
+        for _ in 0..config.gene_count {
+            genes.push(Gene::random(config, width, height, rng));
+        }
+        RandomImage {
+            width,
+            height,
+            pixels,
+            genes,
+            background,
+            fitness: 0.0,
+            errors: vec![],
+        }
+    }
+
+    // A new image whose genes and background are chosen from the reference as
+    // `config.init_strategy` and `config.background_mode` say
+    pub fn new_from_reference(
+        width: u32,
+        height: u32,
+        reference: &Reference,
+        config: &EvolutionConfig,
+        rng: &mut Pcg32,
+    ) -> RandomImage {
+        let background = initial_background(Some(reference), config);
+        let size = (width * height) as usize;
+        RandomImage {
+            width,
+            height,
+            pixels: (0..size).map(|_| Pixel::of_color(&background)).collect(),
+            genes: initial_genes(width, height, reference, config, rng),
+            background,
+            fitness: 0.0,
+            errors: vec![],
+        }
+    }
+
+    pub fn breed_with_rng(
+        &mut self,
+        other: &RandomImage,
+        crossover: CrossoverType,
+        rng: &mut Pcg32,
+    ) {
+        // Parents may have different gene counts. Cut points in `other` are placed
+        // at the same relative position as in `self`, so the child's length ends
+        // up between the parents' lengths.
+        let len = self.genes.len();
+        let other_len = other.genes.len();
+        let map_index = |i: usize| (i * other_len).checked_div(len).unwrap_or(0);
+        let total = self.fitness + other.fitness;
+        let rel_fitness = if total > 0.0 {
+            self.fitness / total
+        } else {
+            0.5
+        };
+        // The background is one more gene: uniform crossover weighs it by
+        // fitness like the others, the point crossovers flip a coin
+        let take_other_background = match crossover {
+            CrossoverType::Uniform => rel_fitness > rng.gen::<f64>(),
+            CrossoverType::OnePoint | CrossoverType::TwoPoint => rng.gen::<bool>(),
+        };
+        if take_other_background {
+            self.background = other.background.clone();
+        }
+        match crossover {
+            CrossoverType::Uniform => {
+                let mut genes = Vec::with_capacity(len.max(other_len));
+                for i in 0..len.max(other_len) {
+                    let take_other = rel_fitness > rng.gen::<f64>();
+                    match (self.genes.get(i), other.genes.get(i)) {
+                        (Some(lhs), Some(rhs)) => {
+                            genes.push(if take_other { rhs.clone() } else { lhs.clone() })
+                        }
+                        (Some(lhs), None) if !take_other => genes.push(lhs.clone()),
+                        (None, Some(rhs)) if take_other => genes.push(rhs.clone()),
+                        _ => {}
+                    }
+                }
+                self.genes = genes;
+            }
+            CrossoverType::OnePoint => {
+                let point = rng.gen_range(0, len + 1);
+                self.genes.truncate(point);
+                self.genes
+                    .extend_from_slice(&other.genes[map_index(point)..other_len]);
+            }
+            CrossoverType::TwoPoint => {
+                let a = rng.gen_range(0, len + 1);
+                let b = rng.gen_range(0, len + 1);
+                let (start, end) = if a < b { (a, b) } else { (b, a) };
+                let middle = &other.genes[map_index(start)..map_index(end)];
+                self.genes.splice(start..end, middle.iter().cloned());
+            }
+        }
+    }
+
+    pub fn mutate_with_rng(&mut self, config: &EvolutionConfig, rng: &mut Pcg32) {
+        self.mutate_guided(config, None, rng);
+    }
+
+    // Mutates with new genes spawned, and single genes picked, preferentially
+    // where `guide` says the error is high. `MutationMode::All` mutates every
+    // gene regardless.
+    pub fn mutate_guided(
+        &mut self,
+        config: &EvolutionConfig,
+        guide: Option<&ErrorGuide>,
+        rng: &mut Pcg32,
+    ) {
+        self.mutate_genes(config, guide, rng);
+        self.mutate_background(config, rng);
+        self.mutate_structure(config, guide, rng);
+    }
+
+    // One change for single-image optimizers: a structural mutation if any
+    // fires, otherwise a background mutation if that fires, otherwise a point
+    // mutation as `mutation_mode` says. `MutationMode::All` is taken as
+    // `Gene` here: moving every gene at once rarely improves a good image.
+    // These optimizers size their steps with the 1/5th success rule, so genes
+    // don't self-adapt their own step sizes on top of it.
+    fn mutate_step(
+        &mut self,
+        config: &EvolutionConfig,
+        guide: Option<&ErrorGuide>,
+        rng: &mut Pcg32,
+    ) {
+        let mutation_mode = match config.mutation_mode {
+            MutationMode::All => MutationMode::Gene,
+            mode => mode,
+        };
+        let config = &EvolutionConfig {
+            self_adaptive: false,
+            mutation_mode,
+            ..*config
+        };
+        if !self.mutate_structure(config, guide, rng) && !self.mutate_background(config, rng) {
+            self.mutate_genes(config, guide, rng);
+        }
+    }
+
+    // The point mutation `mutation_mode` asks for
+    fn mutate_genes(
+        &mut self,
+        config: &EvolutionConfig,
+        guide: Option<&ErrorGuide>,
+        rng: &mut Pcg32,
+    ) {
+        match config.mutation_mode {
+            MutationMode::All => {
+                for gene in &mut self.genes {
+                    gene.mutate(self.width, self.height, config, rng);
+                }
+            }
+            MutationMode::Gene | MutationMode::Component => {
+                self.mutate_one_gene(config, guide, rng)
+            }
+        }
+    }
+
+    // With `BackgroundMode::Evolve`, jitters the background's color (it stays
+    // opaque) with probability `background_mutation_rate`. Returns whether it
+    // did.
+    fn mutate_background(&mut self, config: &EvolutionConfig, rng: &mut Pcg32) -> bool {
+        // No draw at a zero rate, so a fixed background leaves the random
+        // stream as it was
+        if config.background_mode != BackgroundMode::Evolve
+            || config.background_mutation_rate <= 0.0
+            || rng.gen::<f64>() >= config.background_mutation_rate
+        {
+            return false;
+        }
+        for i in 0..3 {
+            self.background.jitter_channel(i, config, rng);
+        }
+        true
+    }
+
+    // A gene to change, picked by `guide` if there is one
+    fn pick_gene(&self, guide: Option<&ErrorGuide>, rng: &mut Pcg32) -> usize {
+        match guide {
+            Some(guide) => guide.pick_gene(&guide.gene_errors(self), rng),
+            None => rng.gen_range(0, self.genes.len()),
+        }
+    }
+
+    fn mutate_one_gene(
+        &mut self,
+        config: &EvolutionConfig,
+        guide: Option<&ErrorGuide>,
+        rng: &mut Pcg32,
+    ) {
+        let idx = self.pick_gene(guide, rng);
+        let (width, height) = (self.width, self.height);
+        match config.mutation_mode {
+            MutationMode::All | MutationMode::Gene => {
+                self.genes[idx].mutate(width, height, config, rng)
+            }
+            MutationMode::Component => {
+                let parts = self.genes[idx].parts(config);
+                let component = GeneComponent::random(config, parts, rng);
+                self.genes[idx].mutate_component(component, width, height, config, rng);
+            }
+        }
+    }
+
+    /*
+    Structural mutations change the number of genes or their draw order, which
+    matters because later shapes are alpha-blended over earlier ones, or the
+    number of vertices of a polygon gene.
+    Each fires independently with its configured probability, and add/remove keep
+    the gene count within [min_genes, max_genes]. Returns whether anything changed.
+    */
+    fn mutate_structure(
+        &mut self,
+        config: &EvolutionConfig,
+        guide: Option<&ErrorGuide>,
+        rng: &mut Pcg32,
+    ) -> bool {
+        let mut changed = false;
+        let max_genes = config.max_genes as usize;
+        let min_genes = config.min_genes as usize;
+
+        if config.add_gene_rate > rng.gen::<f64>() && self.genes.len() < max_genes {
+            let idx = rng.gen_range(0, self.genes.len() + 1);
+            let gene = match guide {
+                Some(guide) => Gene::random_near(config, guide, self.width, self.height, rng),
+                None => Gene::random(config, self.width, self.height, rng),
+            };
+            self.genes.insert(idx, gene);
+            changed = true;
+        }
+        if config.remove_gene_rate > rng.gen::<f64>() && self.genes.len() > min_genes {
+            let idx = rng.gen_range(0, self.genes.len());
+            self.genes.remove(idx);
+            changed = true;
+        }
+        if config.duplicate_gene_rate > rng.gen::<f64>()
+            && !self.genes.is_empty()
+            && self.genes.len() < max_genes
+        {
+            // The jittered copy is drawn just above the original
+            let idx = rng.gen_range(0, self.genes.len());
+            let mut gene = self.genes[idx].clone();
+            gene.mutate(self.width, self.height, config, rng);
+            self.genes.insert(idx + 1, gene);
+            changed = true;
+        }
+        if config.swap_gene_rate > rng.gen::<f64>() && self.genes.len() > 1 {
+            let (a, b) = distinct_indices(self.genes.len(), rng);
+            self.genes.swap(a, b);
+            changed = true;
+        }
+        if config.move_gene_rate > rng.gen::<f64>() && self.genes.len() > 1 {
+            let (from, to) = distinct_indices(self.genes.len(), rng);
+            let gene = self.genes.remove(from);
+            self.genes.insert(to, gene);
+            changed = true;
+        }
+        // Only polygons have vertices to add or remove, and without any the
+        // random stream is left alone
+        let polygons: Vec<usize> = (0..self.genes.len())
+            .filter(|&i| matches!(self.genes[i].geometry, Geometry::Polygon(_)))
+            .collect();
+        if !polygons.is_empty() {
+            if config.add_vertex_rate > rng.gen::<f64>() {
+                let idx = polygons[rng.gen_range(0, polygons.len())];
+                changed |= self.genes[idx]
+                    .geometry
+                    .shape_mut()
+                    .insert_vertex(config, rng);
+            }
+            if config.remove_vertex_rate > rng.gen::<f64>() {
+                let idx = polygons[rng.gen_range(0, polygons.len())];
+                changed |= self.genes[idx].geometry.shape_mut().remove_vertex(rng);
+            }
+        }
+        changed
+    }
+}
+
 #[wasm_bindgen]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Pixel {
     pub r: u8, // <-- pub for test
     g: u8,
@@ -655,6 +1931,10 @@ pub struct Pixel {
 
 #[wasm_bindgen]
 impl Pixel {
+    pub fn new(r: u8, g: u8, b: u8, a: u8) -> Pixel {
+        Pixel { r, g, b, a }
+    }
+
     fn of_color(c: &Color) -> Pixel {
         Pixel {
             r: c.r,
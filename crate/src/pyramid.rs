@@ -0,0 +1,143 @@
+// One level of a Gaussian pyramid of the reference: RGBA bytes, 4 per pixel,
+// and the reference's weight map (if any) resampled to match
+pub struct Level {
+    pub values: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    pub weights: Option<Vec<f64>>,
+}
+
+// Levels smaller than this (in either dimension) are too coarse to be useful
+const MIN_LEVEL_SIZE: u32 = 4;
+
+/*
+Builds up to `levels - 1` successively blurred and roughly halved copies of the
+reference, finest first (the full-size reference itself isn't included).
+Each level is sized so that `RandomImage::shrink` can produce a canvas-sized
+image of exactly the same size, since it only shrinks by whole tiles.
+*/
+pub fn gaussian_pyramid(
+    values: &[u8],
+    width: u32,
+    height: u32,
+    canvas_width: u32,
+    canvas_height: u32,
+    levels: u32,
+) -> Vec<Level> {
+    let mut pyramid: Vec<Level> = vec![];
+    let mut current: Vec<f64> = values.iter().map(|&v| v as f64).collect();
+    let (mut w, mut h) = (width, height);
+    for _ in 1..levels {
+        let (target_w, target_h) = (w / 2, h / 2);
+        if target_w < MIN_LEVEL_SIZE || target_h < MIN_LEVEL_SIZE {
+            break;
+        }
+        let (next_w, next_h) = (
+            shrunk_size(canvas_width, target_w),
+            shrunk_size(canvas_height, target_h),
+        );
+        if next_w >= w || next_h >= h {
+            break;
+        }
+
+        current = resample(&blur(&current, w, h), 4, w, h, next_w, next_h);
+        w = next_w;
+        h = next_h;
+        pyramid.push(Level {
+            values: current.iter().map(|&v| v.round() as u8).collect(),
+            width: w,
+            height: h,
+            weights: None,
+        });
+    }
+    pyramid
+}
+
+// The size `RandomImage::shrink_via_tiling` actually produces when asked to
+// shrink `size` pixels to `target`
+fn shrunk_size(size: u32, target: u32) -> u32 {
+    size / (size / target.max(1)).max(1)
+}
+
+// Separable 5-tap binomial (approximately Gaussian) blur of RGBA values, with
+// edge pixels repeated past the border
+fn blur(values: &[f64], width: u32, height: u32) -> Vec<f64> {
+    const KERNEL: [f64; 5] = [1.0 / 16.0, 4.0 / 16.0, 6.0 / 16.0, 4.0 / 16.0, 1.0 / 16.0];
+    let (w, h) = (width as i64, height as i64);
+    let pass = |input: &[f64], horizontal: bool| {
+        let mut output = vec![0.0; input.len()];
+        for y in 0..h {
+            for x in 0..w {
+                for (k, weight) in KERNEL.iter().enumerate() {
+                    let offset = k as i64 - 2;
+                    let (sx, sy) = if horizontal {
+                        ((x + offset).max(0).min(w - 1), y)
+                    } else {
+                        (x, (y + offset).max(0).min(h - 1))
+                    };
+                    let (src, dst) = (((sy * w + sx) * 4) as usize, ((y * w + x) * 4) as usize);
+                    for c in 0..4 {
+                        output[dst + c] += weight * input[src + c];
+                    }
+                }
+            }
+        }
+        output
+    };
+    pass(&pass(values, true), false)
+}
+
+/*
+Resizes an image with `channels` values per pixel by averaging the source
+pixels that fall in each target pixel (partially covered ones count in
+proportion to how much of them is covered).
+*/
+pub fn resample(
+    values: &[f64],
+    channels: usize,
+    width: u32,
+    height: u32,
+    target_width: u32,
+    target_height: u32,
+) -> Vec<f64> {
+    let scale_x = width as f64 / target_width as f64;
+    let scale_y = height as f64 / target_height as f64;
+    // (source index, fraction covered) for each target row/column
+    let spans = |target: u32, scale: f64| -> Vec<Vec<(usize, f64)>> {
+        (0..target)
+            .map(|t| {
+                let (start, end) = (t as f64 * scale, (t + 1) as f64 * scale);
+                let mut span = vec![];
+                let mut s = start.floor();
+                while s < end {
+                    let covered = (s + 1.0).min(end) - s.max(start);
+                    if covered > 0.0 {
+                        span.push((s as usize, covered));
+                    }
+                    s += 1.0;
+                }
+                span
+            })
+            .collect()
+    };
+    let (cols, rows) = (spans(target_width, scale_x), spans(target_height, scale_y));
+
+    let mut output = Vec::with_capacity((target_width * target_height) as usize * channels);
+    for row in rows.iter() {
+        for col in cols.iter() {
+            let mut sum = vec![0.0; channels];
+            let mut total = 0.0;
+            for &(sy, fy) in row.iter() {
+                for &(sx, fx) in col.iter() {
+                    let i = (sy * width as usize + sx) * channels;
+                    for c in 0..channels {
+                        sum[c] += fx * fy * values[i + c];
+                    }
+                    total += fx * fy;
+                }
+            }
+            output.extend(sum.iter().map(|s| s / total));
+        }
+    }
+    output
+}
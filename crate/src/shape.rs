@@ -0,0 +1,1389 @@
+use distribution::{perturb, random_step, BoundaryMode};
+use nalgebra::{Point2, Vector3};
+use rand::Rng;
+use rand_pcg::Pcg32;
+use std::f64::consts::PI;
+use wasm_bindgen::prelude::*;
+use {clamp, EvolutionConfig};
+
+/*
+Which shapes new genes get: all one kind, or `Mixed`, where each new gene's
+kind is picked uniformly.
+*/
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub enum ShapeKind {
+    Triangle,
+    Circle,
+    Ellipse,
+    RotatedEllipse,
+    RotatedRect,
+    Polygon,
+    QuadraticStroke,
+    CubicStroke,
+    Mixed,
+}
+
+/*
+Which points a self-intersecting polygon fills. Casting a ray from a point,
+`EvenOdd` fills it if the ray crosses the outline an odd number of times, so
+overlapping loops leave holes; `NonZero` fills it if the outline winds around
+it at all, counting crossings in each direction as +1 and -1.
+*/
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum FillRule {
+    EvenOdd,
+    NonZero,
+}
+
+impl FillRule {
+    fn fills(self, crossings: usize, winding: i32) -> bool {
+        match self {
+            FillRule::EvenOdd => crossings % 2 == 1,
+            FillRule::NonZero => winding != 0,
+        }
+    }
+}
+
+// A rectangle of pixels, from `origin` up to but not including `extent`;
+// iterating it visits the pixels inside
+#[derive(Clone)]
+pub struct BBox {
+    origin: Point2<u32>,
+    extent: Point2<u32>,
+
+    // For iterator
+    _iter_x: u32,
+    _iter_y: u32,
+}
+
+impl BBox {
+    pub fn xmin(&self) -> u32 {
+        self.origin.x
+    }
+    pub fn xmax(&self) -> u32 {
+        self.extent.x
+    }
+    pub fn ymin(&self) -> u32 {
+        self.origin.y
+    }
+    pub fn ymax(&self) -> u32 {
+        self.extent.y
+    }
+
+    // The smallest box around both
+    pub fn union(&self, other: &BBox) -> BBox {
+        let (min_x, min_y) = (self.xmin().min(other.xmin()), self.ymin().min(other.ymin()));
+        BBox {
+            origin: Point2::new(min_x, min_y),
+            extent: Point2::new(self.xmax().max(other.xmax()), self.ymax().max(other.ymax())),
+            _iter_x: min_x,
+            _iter_y: min_y,
+        }
+    }
+
+    pub fn intersects(&self, other: &BBox) -> bool {
+        self.xmin() < other.xmax()
+            && other.xmin() < self.xmax()
+            && self.ymin() < other.ymax()
+            && other.ymin() < self.ymax()
+    }
+
+    pub fn contains(&self, p: &Point2<u32>) -> bool {
+        p.x >= self.xmin() && p.x < self.xmax() && p.y >= self.ymin() && p.y < self.ymax()
+    }
+
+    // The box of `min_x..=max_x` by `min_y..=max_y`
+    pub fn spanning(min_x: u32, min_y: u32, max_x: u32, max_y: u32) -> BBox {
+        BBox {
+            origin: Point2::new(min_x, min_y),
+            extent: Point2::new(max_x + 1, max_y + 1),
+            _iter_x: min_x,
+            _iter_y: min_y,
+        }
+    }
+}
+
+impl Iterator for BBox {
+    type Item = Point2<u32>;
+
+    // Rows top to bottom, each left to right; the position after the last
+    // pixel is the start of the row below the box
+    fn next(&mut self) -> Option<Point2<u32>> {
+        if self._iter_y >= self.ymax() || self.xmin() >= self.xmax() {
+            return None;
+        }
+        let cur = Point2::new(self._iter_x, self._iter_y);
+        self._iter_x += 1;
+        if self._iter_x >= self.xmax() {
+            self._iter_x = self.xmin();
+            self._iter_y += 1;
+        }
+        Some(cur)
+    }
+}
+
+/*
+Where shapes are. Positions are fractions of the canvas, x of its width and y
+of its height, so (0, 0) is the top left corner and (1, 1) the bottom right;
+lengths (radii, half-sizes, pen widths) are fractions of its longer side.
+Nothing about a shape depends on the canvas size until it's rasterized, so
+the same genome draws the same picture at any size, and mutations can move
+shapes by less than a pixel. Pixels are sampled at their centers.
+*/
+
+// How far past the canvas edges positions may go, as a fraction of it, so
+// shapes can reach the edges without being squeezed against them
+pub const OVERSHOOT: f64 = 0.1;
+
+// The canvas's longer side, in pixels: what lengths are fractions of
+pub fn scale(width: u32, height: u32) -> f64 {
+    width.max(height) as f64
+}
+
+// Position `p` on a `width` x `height` canvas, in pixels
+pub fn to_pixels(p: &Point2<f32>, width: u32, height: u32) -> (f64, f64) {
+    (p.x as f64 * width as f64, p.y as f64 * height as f64)
+}
+
+// The position of the center of pixel `p`
+pub fn pixel_position(p: &Point2<u32>, width: u32, height: u32) -> Point2<f32> {
+    Point2::new(
+        ((p.x as f64 + 0.5) / width as f64) as f32,
+        ((p.y as f64 + 0.5) / height as f64) as f32,
+    )
+}
+
+// Where pixel `p` is sampled, in pixels
+pub fn pixel_center(p: &Point2<u32>) -> (f64, f64) {
+    (p.x as f64 + 0.5, p.y as f64 + 0.5)
+}
+
+/*
+What a gene draws. Shapes may reach past the canvas edges; only the part on
+the canvas is drawn. Methods that deal in pixels take the size of the canvas
+they're for.
+Each shape makes random ones with its own `random` (anywhere on the canvas)
+and `random_near` (roughly within `spread`, fractions of the width and height,
+of `center`); polygons and strokes only have the latter, which takes the config
+their vertex count and taper come from.
+*/
+pub trait Shape {
+    // How many parameters (a vertex, the center, a radius, the angle...)
+    // `jitter_part` can change one at a time under `config`
+    fn parts(&self, config: &EvolutionConfig) -> usize;
+
+    fn jitter_part(
+        &mut self,
+        i: usize,
+        width: u32,
+        height: u32,
+        config: &EvolutionConfig,
+        rng: &mut Pcg32,
+    );
+
+    // Moves the whole shape without changing its size or angle
+    fn translate(&mut self, width: u32, height: u32, config: &EvolutionConfig, rng: &mut Pcg32);
+
+    // The pixels worth testing with `contains`, all on the canvas
+    fn bbox(&self, width: u32, height: u32) -> BBox;
+
+    fn contains(&self, p: &Point2<u32>, width: u32, height: u32) -> bool;
+
+    // As a position, like the vertices
+    fn center(&self) -> (f64, f64);
+
+    // Adds or removes a vertex, for shapes that can have any number of them.
+    // Both return whether they did.
+    fn insert_vertex(&mut self, _config: &EvolutionConfig, _rng: &mut Pcg32) -> bool {
+        false
+    }
+
+    fn remove_vertex(&mut self, _rng: &mut Pcg32) -> bool {
+        false
+    }
+
+    fn mutate(&mut self, width: u32, height: u32, config: &EvolutionConfig, rng: &mut Pcg32) {
+        for i in 0..self.parts(config) {
+            self.jitter_part(i, width, height, config, rng);
+        }
+    }
+
+    // Calls `f` with each pixel the shape covers, skipping any outside `clip`
+    fn rasterize(
+        &self,
+        width: u32,
+        height: u32,
+        clip: Option<&BBox>,
+        f: &mut dyn FnMut(Point2<u32>),
+    ) {
+        for p in self.bbox(width, height) {
+            if let Some(clip) = clip {
+                if !clip.contains(&p) {
+                    continue;
+                }
+            }
+            if self.contains(&p, width, height) {
+                f(p);
+            }
+        }
+    }
+
+    // Like `rasterize`, also passing how much of each pixel is covered, in
+    // (0, 1]. Only shapes with antialiased edges cover pixels partly.
+    fn rasterize_coverage(
+        &self,
+        width: u32,
+        height: u32,
+        clip: Option<&BBox>,
+        f: &mut dyn FnMut(Point2<u32>, f64),
+    ) {
+        self.rasterize(width, height, clip, &mut |p| f(p, 1.0));
+    }
+}
+
+// Anywhere on the canvas
+pub fn random_point(rng: &mut Pcg32) -> Point2<f32> {
+    Point2::new(rng.gen::<f32>(), rng.gen::<f32>())
+}
+
+// A point within `spread` of `center`, kept on the canvas
+fn random_point_near(center: Point2<f32>, spread: (f64, f64), rng: &mut Pcg32) -> Point2<f32> {
+    let x = center.x as f64 + rng.gen_range(-1.0, 1.0) * spread.0;
+    let y = center.y as f64 + rng.gen_range(-1.0, 1.0) * spread.1;
+    Point2::new(clamp(x, 0.0, 1.0) as f32, clamp(y, 0.0, 1.0) as f32)
+}
+
+// vertex_jitter is the full width of the window a point can move in, as a
+// fraction of the canvas
+pub fn jitter_point(p: &mut Point2<f32>, config: &EvolutionConfig, rng: &mut Pcg32) {
+    let jitter = config.vertex_jitter / 2.0;
+    let (distribution, boundary) = (config.mutation_distribution, config.vertex_boundary);
+    let (lo, hi) = (-OVERSHOOT, 1.0 + OVERSHOOT);
+    p.x = perturb(p.x as f64, jitter, lo, hi, distribution, boundary, rng) as f32;
+    p.y = perturb(p.y as f64, jitter, lo, hi, distribution, boundary, rng) as f32;
+}
+
+// Moves all `vertices` by the same offset, limited so that no vertex goes
+// further past the edges than `OVERSHOOT` (which would distort the shape)
+fn translate_vertices(vertices: &mut [Point2<f32>], config: &EvolutionConfig, rng: &mut Pcg32) {
+    fn offset(vs: &[f64], config: &EvolutionConfig, rng: &mut Pcg32) -> f64 {
+        let lo = -OVERSHOOT - vs.iter().cloned().fold(1.0, f64::min);
+        let hi = 1.0 + OVERSHOOT - vs.iter().cloned().fold(0.0, f64::max);
+        let step = random_step(
+            config.vertex_jitter / 2.0,
+            config.mutation_distribution,
+            rng,
+        );
+        clamp(step, lo.min(0.0), hi.max(0.0))
+    }
+
+    let xs: Vec<f64> = vertices.iter().map(|v| v.x as f64).collect();
+    let ys: Vec<f64> = vertices.iter().map(|v| v.y as f64).collect();
+    let dx = offset(&xs, config, rng);
+    let dy = offset(&ys, config, rng);
+    for v in vertices.iter_mut() {
+        v.x = (v.x as f64 + dx) as f32;
+        v.y = (v.y as f64 + dy) as f32;
+    }
+}
+
+// Moves a center by a random offset, keeping it within `OVERSHOOT` of the
+// canvas
+fn translate_point(p: &mut Point2<f32>, config: &EvolutionConfig, rng: &mut Pcg32) {
+    let jitter = config.vertex_jitter / 2.0;
+    let dx = random_step(jitter, config.mutation_distribution, rng);
+    let dy = random_step(jitter, config.mutation_distribution, rng);
+    p.x = clamp(p.x as f64 + dx, -OVERSHOOT, 1.0 + OVERSHOOT) as f32;
+    p.y = clamp(p.y as f64 + dy, -OVERSHOOT, 1.0 + OVERSHOOT) as f32;
+}
+
+/*
+Lengths are at least a pixel and at most half the canvas. The pixel is one of
+the canvas being evolved: lengths are stored as fractions, so the clamp is
+applied in those terms when a shape is made or mutated, and the same genome
+drawn larger with `render_at` has shapes more than a pixel thin. Drawing it
+smaller may leave the thinnest ones under a pixel, where they can miss every
+pixel center.
+*/
+fn min_length(width: u32, height: u32) -> f64 {
+    1.0 / scale(width, height)
+}
+
+const MAX_LENGTH: f64 = 0.5;
+
+// Up to `max`, a fraction of the longer side
+pub fn random_length(max: f64, width: u32, height: u32, rng: &mut Pcg32) -> f32 {
+    let min = min_length(width, height);
+    if max > min {
+        rng.gen_range(min, max) as f32
+    } else {
+        min as f32
+    }
+}
+
+pub fn jitter_length(
+    v: &mut f32,
+    width: u32,
+    height: u32,
+    config: &EvolutionConfig,
+    rng: &mut Pcg32,
+) {
+    *v = perturb(
+        *v as f64,
+        config.vertex_jitter / 2.0,
+        min_length(width, height),
+        MAX_LENGTH,
+        config.mutation_distribution,
+        config.vertex_boundary,
+        rng,
+    ) as f32;
+}
+
+// Ellipses and rectangles look the same turned half a turn, so angles wrap
+// around in [0, π). vertex_jitter scales the step as it does for positions.
+fn jitter_angle(angle: &mut f64, config: &EvolutionConfig, rng: &mut Pcg32) {
+    *angle = perturb(
+        *angle,
+        config.vertex_jitter * PI,
+        0.0,
+        PI,
+        config.mutation_distribution,
+        BoundaryMode::Wrap,
+        rng,
+    );
+}
+
+// The pixels within `reach` pixels of the box around `points` (in pixels),
+// clipped to the canvas
+fn pixel_bbox(points: &[(f64, f64)], reach: f64, width: u32, height: u32) -> BBox {
+    let min_x = points.iter().map(|p| p.0).fold(points[0].0, f64::min);
+    let min_y = points.iter().map(|p| p.1).fold(points[0].1, f64::min);
+    let max_x = points.iter().map(|p| p.0).fold(points[0].0, f64::max);
+    let max_y = points.iter().map(|p| p.1).fold(points[0].1, f64::max);
+    let clip = |v: f64, max: u32| clamp(v, 0.0, (max - 1) as f64) as u32;
+    BBox::spanning(
+        clip((min_x - reach).floor(), width),
+        clip((min_y - reach).floor(), height),
+        clip((max_x + reach).ceil(), width),
+        clip((max_y + reach).ceil(), height),
+    )
+}
+
+// The box reaching `extent_x` and `extent_y` pixels either side of `center`,
+// clipped to the canvas
+fn centered_bbox(
+    center: &Point2<f32>,
+    extent_x: f64,
+    extent_y: f64,
+    width: u32,
+    height: u32,
+) -> BBox {
+    let (cx, cy) = to_pixels(center, width, height);
+    pixel_bbox(
+        &[
+            (cx - extent_x, cy - extent_y),
+            (cx + extent_x, cy + extent_y),
+        ],
+        0.0,
+        width,
+        height,
+    )
+}
+
+// Pixel `p` relative to `center`, in pixels, in a frame turned by `angle`
+fn local_coordinates(
+    p: &Point2<u32>,
+    center: &Point2<f32>,
+    angle: f64,
+    width: u32,
+    height: u32,
+) -> (f64, f64) {
+    let ((px, py), (cx, cy)) = (pixel_center(p), to_pixels(center, width, height));
+    let (dx, dy) = (px - cx, py - cy);
+    let (sin, cos) = angle.sin_cos();
+    (dx * cos + dy * sin, dy * cos - dx * sin)
+}
+
+// The mean of `points`
+fn centroid(points: &[Point2<f32>]) -> (f64, f64) {
+    let n = points.len() as f64;
+    let x: f64 = points.iter().map(|v| v.x as f64).sum();
+    let y: f64 = points.iter().map(|v| v.y as f64).sum();
+    (x / n, y / n)
+}
+
+#[derive(Clone, PartialEq, Serialize)]
+pub struct Triangle {
+    v0: Point2<f32>,
+    v1: Point2<f32>,
+    v2: Point2<f32>,
+}
+
+impl Triangle {
+    pub fn new(v0: Point2<f32>, v1: Point2<f32>, v2: Point2<f32>) -> Triangle {
+        Triangle { v0, v1, v2 }
+    }
+
+    pub fn random(_width: u32, _height: u32, rng: &mut Pcg32) -> Triangle {
+        Triangle::new(random_point(rng), random_point(rng), random_point(rng))
+    }
+
+    // Each vertex lands within `spread` of `center`
+    pub fn random_near(
+        center: Point2<f32>,
+        spread: (f64, f64),
+        _width: u32,
+        _height: u32,
+        rng: &mut Pcg32,
+    ) -> Triangle {
+        let mut vertex = || random_point_near(center, spread, rng);
+        let (v0, v1, v2) = (vertex(), vertex(), vertex());
+        Triangle::new(v0, v1, v2)
+    }
+
+    fn vertex_mut(&mut self, i: usize) -> &mut Point2<f32> {
+        match i {
+            0 => &mut self.v0,
+            1 => &mut self.v1,
+            _ => &mut self.v2,
+        }
+    }
+
+    /*
+    Cross product
+        [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0]
+    ]
+    Only a triangle of next to no area, relative to the canvas, counts as
+    degenerate, so thin ones are drawn the same at any size.
+    */
+    fn barycentric(&self, p: &Point2<u32>, width: u32, height: u32) -> Option<Vector3<f64>> {
+        let (px, py) = pixel_center(p);
+        let (x0, y0) = to_pixels(&self.v0, width, height);
+        let (x1, y1) = to_pixels(&self.v1, width, height);
+        let (x2, y2) = to_pixels(&self.v2, width, height);
+        let v1 = Vector3::new(x2 - x0, x1 - x0, x0 - px);
+        let v2 = Vector3::new(y2 - y0, y1 - y0, y0 - py);
+        let u = Vector3::new(
+            v1.y * v2.z - v1.z * v2.y,
+            v1.z * v2.x - v1.x * v2.z,
+            v1.x * v2.y - v1.y * v2.x,
+        );
+        // u.z is twice the area, in pixels
+        if u.z.abs() < 1e-12 * width as f64 * height as f64 {
+            None
+        } else {
+            Some(Vector3::new(1.0 - (u.x + u.y) / u.z, u.y / u.z, u.x / u.z))
+        }
+    }
+}
+
+impl Shape for Triangle {
+    fn parts(&self, _config: &EvolutionConfig) -> usize {
+        3
+    }
+
+    fn jitter_part(
+        &mut self,
+        i: usize,
+        _width: u32,
+        _height: u32,
+        config: &EvolutionConfig,
+        rng: &mut Pcg32,
+    ) {
+        jitter_point(self.vertex_mut(i), config, rng);
+    }
+
+    fn translate(&mut self, _width: u32, _height: u32, config: &EvolutionConfig, rng: &mut Pcg32) {
+        let mut vertices = [self.v0, self.v1, self.v2];
+        translate_vertices(&mut vertices, config, rng);
+        self.v0 = vertices[0];
+        self.v1 = vertices[1];
+        self.v2 = vertices[2];
+    }
+
+    fn bbox(&self, width: u32, height: u32) -> BBox {
+        let vertices = [
+            to_pixels(&self.v0, width, height),
+            to_pixels(&self.v1, width, height),
+            to_pixels(&self.v2, width, height),
+        ];
+        pixel_bbox(&vertices, 0.0, width, height)
+    }
+
+    fn contains(&self, p: &Point2<u32>, width: u32, height: u32) -> bool {
+        match self.barycentric(p, width, height) {
+            Some(v3) => v3.x > 0.0 && v3.y > 0.0 && v3.z > 0.0,
+            _ => false,
+        }
+    }
+
+    fn center(&self) -> (f64, f64) {
+        centroid(&[self.v0, self.v1, self.v2])
+    }
+}
+
+#[derive(Clone, PartialEq, Serialize)]
+pub struct Circle {
+    center: Point2<f32>,
+    radius: f32,
+}
+
+impl Circle {
+    pub fn new(center: Point2<f32>, radius: f32) -> Circle {
+        Circle { center, radius }
+    }
+
+    pub fn random(width: u32, height: u32, rng: &mut Pcg32) -> Circle {
+        let max = width.min(height) as f64 / 4.0 / scale(width, height);
+        Circle {
+            center: random_point(rng),
+            radius: random_length(max, width, height, rng),
+        }
+    }
+
+    pub fn random_near(
+        center: Point2<f32>,
+        spread: (f64, f64),
+        width: u32,
+        height: u32,
+        rng: &mut Pcg32,
+    ) -> Circle {
+        let max = (spread.0 * width as f64).min(spread.1 * height as f64) / scale(width, height);
+        Circle {
+            center,
+            radius: random_length(max, width, height, rng),
+        }
+    }
+}
+
+impl Shape for Circle {
+    // The center, then the radius
+    fn parts(&self, _config: &EvolutionConfig) -> usize {
+        2
+    }
+
+    fn jitter_part(
+        &mut self,
+        i: usize,
+        width: u32,
+        height: u32,
+        config: &EvolutionConfig,
+        rng: &mut Pcg32,
+    ) {
+        match i {
+            0 => jitter_point(&mut self.center, config, rng),
+            _ => jitter_length(&mut self.radius, width, height, config, rng),
+        }
+    }
+
+    fn translate(&mut self, _width: u32, _height: u32, config: &EvolutionConfig, rng: &mut Pcg32) {
+        translate_point(&mut self.center, config, rng);
+    }
+
+    fn bbox(&self, width: u32, height: u32) -> BBox {
+        let r = self.radius as f64 * scale(width, height);
+        centered_bbox(&self.center, r, r, width, height)
+    }
+
+    fn contains(&self, p: &Point2<u32>, width: u32, height: u32) -> bool {
+        let (x, y) = local_coordinates(p, &self.center, 0.0, width, height);
+        let r = self.radius as f64 * scale(width, height);
+        x * x + y * y < r * r
+    }
+
+    fn center(&self) -> (f64, f64) {
+        (self.center.x as f64, self.center.y as f64)
+    }
+}
+
+// An ellipse with its axes along x and y
+#[derive(Clone, PartialEq, Serialize)]
+pub struct Ellipse {
+    center: Point2<f32>,
+    radius_x: f32,
+    radius_y: f32,
+}
+
+impl Ellipse {
+    pub fn random(width: u32, height: u32, rng: &mut Pcg32) -> Ellipse {
+        let s = scale(width, height);
+        Ellipse {
+            center: random_point(rng),
+            radius_x: random_length(width as f64 / 4.0 / s, width, height, rng),
+            radius_y: random_length(height as f64 / 4.0 / s, width, height, rng),
+        }
+    }
+
+    pub fn random_near(
+        center: Point2<f32>,
+        spread: (f64, f64),
+        width: u32,
+        height: u32,
+        rng: &mut Pcg32,
+    ) -> Ellipse {
+        let s = scale(width, height);
+        Ellipse {
+            center,
+            radius_x: random_length(spread.0 * width as f64 / s, width, height, rng),
+            radius_y: random_length(spread.1 * height as f64 / s, width, height, rng),
+        }
+    }
+}
+
+impl Shape for Ellipse {
+    // The center, then each radius
+    fn parts(&self, _config: &EvolutionConfig) -> usize {
+        3
+    }
+
+    fn jitter_part(
+        &mut self,
+        i: usize,
+        width: u32,
+        height: u32,
+        config: &EvolutionConfig,
+        rng: &mut Pcg32,
+    ) {
+        match i {
+            0 => jitter_point(&mut self.center, config, rng),
+            1 => jitter_length(&mut self.radius_x, width, height, config, rng),
+            _ => jitter_length(&mut self.radius_y, width, height, config, rng),
+        }
+    }
+
+    fn translate(&mut self, _width: u32, _height: u32, config: &EvolutionConfig, rng: &mut Pcg32) {
+        translate_point(&mut self.center, config, rng);
+    }
+
+    fn bbox(&self, width: u32, height: u32) -> BBox {
+        let s = scale(width, height);
+        centered_bbox(
+            &self.center,
+            self.radius_x as f64 * s,
+            self.radius_y as f64 * s,
+            width,
+            height,
+        )
+    }
+
+    fn contains(&self, p: &Point2<u32>, width: u32, height: u32) -> bool {
+        let s = scale(width, height);
+        let (x, y) = local_coordinates(p, &self.center, 0.0, width, height);
+        let (x, y) = (
+            x / (self.radius_x as f64 * s),
+            y / (self.radius_y as f64 * s),
+        );
+        x * x + y * y < 1.0
+    }
+
+    fn center(&self) -> (f64, f64) {
+        (self.center.x as f64, self.center.y as f64)
+    }
+}
+
+// An ellipse turned `angle` radians clockwise (y points down)
+#[derive(Clone, PartialEq, Serialize)]
+pub struct RotatedEllipse {
+    center: Point2<f32>,
+    radius_x: f32,
+    radius_y: f32,
+    angle: f64,
+}
+
+impl RotatedEllipse {
+    pub fn random(width: u32, height: u32, rng: &mut Pcg32) -> RotatedEllipse {
+        let length = width.min(height) as f64 / 4.0 / scale(width, height);
+        RotatedEllipse {
+            center: random_point(rng),
+            radius_x: random_length(length, width, height, rng),
+            radius_y: random_length(length, width, height, rng),
+            angle: rng.gen_range(0.0, PI),
+        }
+    }
+
+    pub fn random_near(
+        center: Point2<f32>,
+        spread: (f64, f64),
+        width: u32,
+        height: u32,
+        rng: &mut Pcg32,
+    ) -> RotatedEllipse {
+        let length = (spread.0 * width as f64).min(spread.1 * height as f64) / scale(width, height);
+        RotatedEllipse {
+            center,
+            radius_x: random_length(length, width, height, rng),
+            radius_y: random_length(length, width, height, rng),
+            angle: rng.gen_range(0.0, PI),
+        }
+    }
+}
+
+impl Shape for RotatedEllipse {
+    // The center, each radius, then the angle
+    fn parts(&self, _config: &EvolutionConfig) -> usize {
+        4
+    }
+
+    fn jitter_part(
+        &mut self,
+        i: usize,
+        width: u32,
+        height: u32,
+        config: &EvolutionConfig,
+        rng: &mut Pcg32,
+    ) {
+        match i {
+            0 => jitter_point(&mut self.center, config, rng),
+            1 => jitter_length(&mut self.radius_x, width, height, config, rng),
+            2 => jitter_length(&mut self.radius_y, width, height, config, rng),
+            _ => jitter_angle(&mut self.angle, config, rng),
+        }
+    }
+
+    fn translate(&mut self, _width: u32, _height: u32, config: &EvolutionConfig, rng: &mut Pcg32) {
+        translate_point(&mut self.center, config, rng);
+    }
+
+    fn bbox(&self, width: u32, height: u32) -> BBox {
+        let s = scale(width, height);
+        let (rx, ry) = (self.radius_x as f64 * s, self.radius_y as f64 * s);
+        let (sin, cos) = self.angle.sin_cos();
+        centered_bbox(
+            &self.center,
+            ((rx * cos).powi(2) + (ry * sin).powi(2)).sqrt(),
+            ((rx * sin).powi(2) + (ry * cos).powi(2)).sqrt(),
+            width,
+            height,
+        )
+    }
+
+    fn contains(&self, p: &Point2<u32>, width: u32, height: u32) -> bool {
+        let s = scale(width, height);
+        let (x, y) = local_coordinates(p, &self.center, self.angle, width, height);
+        let (x, y) = (
+            x / (self.radius_x as f64 * s),
+            y / (self.radius_y as f64 * s),
+        );
+        x * x + y * y < 1.0
+    }
+
+    fn center(&self) -> (f64, f64) {
+        (self.center.x as f64, self.center.y as f64)
+    }
+}
+
+// A rectangle `2 * half_width` by `2 * half_height`, turned `angle` radians
+// clockwise about its center
+#[derive(Clone, PartialEq, Serialize)]
+pub struct RotatedRect {
+    center: Point2<f32>,
+    half_width: f32,
+    half_height: f32,
+    angle: f64,
+}
+
+impl RotatedRect {
+    pub fn new(center: Point2<f32>, half_width: f32, half_height: f32, angle: f64) -> RotatedRect {
+        RotatedRect {
+            center,
+            half_width,
+            half_height,
+            angle,
+        }
+    }
+
+    pub fn random(width: u32, height: u32, rng: &mut Pcg32) -> RotatedRect {
+        let length = width.min(height) as f64 / 4.0 / scale(width, height);
+        RotatedRect {
+            center: random_point(rng),
+            half_width: random_length(length, width, height, rng),
+            half_height: random_length(length, width, height, rng),
+            angle: rng.gen_range(0.0, PI),
+        }
+    }
+
+    pub fn random_near(
+        center: Point2<f32>,
+        spread: (f64, f64),
+        width: u32,
+        height: u32,
+        rng: &mut Pcg32,
+    ) -> RotatedRect {
+        let length = (spread.0 * width as f64).min(spread.1 * height as f64) / scale(width, height);
+        RotatedRect {
+            center,
+            half_width: random_length(length, width, height, rng),
+            half_height: random_length(length, width, height, rng),
+            angle: rng.gen_range(0.0, PI),
+        }
+    }
+}
+
+impl Shape for RotatedRect {
+    // The center, each half-size, then the angle
+    fn parts(&self, _config: &EvolutionConfig) -> usize {
+        4
+    }
+
+    fn jitter_part(
+        &mut self,
+        i: usize,
+        width: u32,
+        height: u32,
+        config: &EvolutionConfig,
+        rng: &mut Pcg32,
+    ) {
+        match i {
+            0 => jitter_point(&mut self.center, config, rng),
+            1 => jitter_length(&mut self.half_width, width, height, config, rng),
+            2 => jitter_length(&mut self.half_height, width, height, config, rng),
+            _ => jitter_angle(&mut self.angle, config, rng),
+        }
+    }
+
+    fn translate(&mut self, _width: u32, _height: u32, config: &EvolutionConfig, rng: &mut Pcg32) {
+        translate_point(&mut self.center, config, rng);
+    }
+
+    fn bbox(&self, width: u32, height: u32) -> BBox {
+        let s = scale(width, height);
+        let (hw, hh) = (self.half_width as f64 * s, self.half_height as f64 * s);
+        let (sin, cos) = self.angle.sin_cos();
+        let (sin, cos) = (sin.abs(), cos.abs());
+        centered_bbox(
+            &self.center,
+            hw * cos + hh * sin,
+            hw * sin + hh * cos,
+            width,
+            height,
+        )
+    }
+
+    fn contains(&self, p: &Point2<u32>, width: u32, height: u32) -> bool {
+        let s = scale(width, height);
+        let (x, y) = local_coordinates(p, &self.center, self.angle, width, height);
+        x.abs() < self.half_width as f64 * s && y.abs() < self.half_height as f64 * s
+    }
+
+    fn center(&self) -> (f64, f64) {
+        (self.center.x as f64, self.center.y as f64)
+    }
+}
+
+/*
+A polygon with 3 to `EvolutionConfig::max_polygon_vertices` vertices, filled by
+its `fill_rule` where its outline crosses itself.
+*/
+#[derive(Clone, PartialEq, Serialize)]
+pub struct Polygon {
+    vertices: Vec<Point2<f32>>,
+    fill_rule: FillRule,
+}
+
+impl Polygon {
+    pub fn new(vertices: Vec<Point2<f32>>, fill_rule: FillRule) -> Polygon {
+        Polygon {
+            vertices,
+            fill_rule,
+        }
+    }
+
+    /*
+    A random polygon with between 3 and `max_polygon_vertices` vertices, placed
+    in order of angle around `center` and each within `spread` of it, so it
+    starts out not crossing itself.
+    */
+    pub fn random_near(
+        config: &EvolutionConfig,
+        center: Point2<f32>,
+        spread: (f64, f64),
+        rng: &mut Pcg32,
+    ) -> Polygon {
+        let count = rng.gen_range(3, config.max_polygon_vertices as usize + 1);
+        let mut angles: Vec<f64> = (0..count).map(|_| rng.gen_range(0.0, 2.0 * PI)).collect();
+        angles.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let vertices = angles
+            .iter()
+            .map(|angle| {
+                let distance = rng.gen_range(0.25, 1.0);
+                let (sin, cos) = angle.sin_cos();
+                let x = center.x as f64 + cos * distance * spread.0;
+                let y = center.y as f64 + sin * distance * spread.1;
+                Point2::new(clamp(x, 0.0, 1.0) as f32, clamp(y, 0.0, 1.0) as f32)
+            })
+            .collect();
+        Polygon {
+            vertices,
+            fill_rule: config.polygon_fill_rule,
+        }
+    }
+
+    fn pixel_vertices(&self, width: u32, height: u32) -> Vec<(f64, f64)> {
+        self.vertices
+            .iter()
+            .map(|v| to_pixels(v, width, height))
+            .collect()
+    }
+
+    // Where the outline through `vertices` (in pixels) crosses the line `y`:
+    // the x of each crossing, and +1 or -1 for an edge going down or up. An
+    // edge covers the lines from its upper vertex to just above its lower one,
+    // so a vertex on the line is only counted once.
+    fn crossings(vertices: &[(f64, f64)], y: f64) -> Vec<(f64, i32)> {
+        let n = vertices.len();
+        let mut crossings = vec![];
+        for i in 0..n {
+            let ((ax, ay), (bx, by)) = (vertices[i], vertices[(i + 1) % n]);
+            if (ay > y) != (by > y) {
+                let x = ax + (y - ay) * (bx - ax) / (by - ay);
+                crossings.push((x, if by > ay { 1 } else { -1 }));
+            }
+        }
+        crossings
+    }
+}
+
+impl Shape for Polygon {
+    fn parts(&self, _config: &EvolutionConfig) -> usize {
+        self.vertices.len()
+    }
+
+    fn jitter_part(
+        &mut self,
+        i: usize,
+        _width: u32,
+        _height: u32,
+        config: &EvolutionConfig,
+        rng: &mut Pcg32,
+    ) {
+        jitter_point(&mut self.vertices[i], config, rng);
+    }
+
+    fn translate(&mut self, _width: u32, _height: u32, config: &EvolutionConfig, rng: &mut Pcg32) {
+        translate_vertices(&mut self.vertices, config, rng);
+    }
+
+    fn bbox(&self, width: u32, height: u32) -> BBox {
+        pixel_bbox(&self.pixel_vertices(width, height), 0.0, width, height)
+    }
+
+    // Casts the ray toward +x
+    fn contains(&self, p: &Point2<u32>, width: u32, height: u32) -> bool {
+        let (px, py) = pixel_center(p);
+        let (mut count, mut winding) = (0, 0);
+        for (x, direction) in Polygon::crossings(&self.pixel_vertices(width, height), py) {
+            if x > px {
+                count += 1;
+                winding += direction;
+            }
+        }
+        self.fill_rule.fills(count, winding)
+    }
+
+    fn center(&self) -> (f64, f64) {
+        centroid(&self.vertices)
+    }
+
+    // Splits a random edge at a jittered midpoint, so the outline barely
+    // changes until later mutations move the new vertex
+    fn insert_vertex(&mut self, config: &EvolutionConfig, rng: &mut Pcg32) -> bool {
+        let n = self.vertices.len();
+        if n >= config.max_polygon_vertices as usize {
+            return false;
+        }
+        let i = rng.gen_range(0, n);
+        let (a, b) = (self.vertices[i], self.vertices[(i + 1) % n]);
+        let mut vertex = Point2::new((a.x + b.x) / 2.0, (a.y + b.y) / 2.0);
+        jitter_point(&mut vertex, config, rng);
+        self.vertices.insert(i + 1, vertex);
+        true
+    }
+
+    fn remove_vertex(&mut self, rng: &mut Pcg32) -> bool {
+        if self.vertices.len() <= 3 {
+            return false;
+        }
+        let i = rng.gen_range(0, self.vertices.len());
+        self.vertices.remove(i);
+        true
+    }
+
+    /*
+    Scanline fill: each row's crossings are found once and sorted, then the row
+    is swept left to right, dropping crossings as they fall behind. That leaves
+    the crossings to the right of each pixel, the same ones `contains` counts.
+    */
+    fn rasterize(
+        &self,
+        width: u32,
+        height: u32,
+        clip: Option<&BBox>,
+        f: &mut dyn FnMut(Point2<u32>),
+    ) {
+        let vertices = self.pixel_vertices(width, height);
+        let bbox = pixel_bbox(&vertices, 0.0, width, height);
+        for y in bbox.ymin()..bbox.ymax() {
+            let mut crossings = Polygon::crossings(&vertices, y as f64 + 0.5);
+            crossings.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+            let mut count = crossings.len();
+            let mut winding: i32 = crossings.iter().map(|c| c.1).sum();
+            let mut passed = 0;
+            for x in bbox.xmin()..bbox.xmax() {
+                while passed < crossings.len() && crossings[passed].0 <= x as f64 + 0.5 {
+                    count -= 1;
+                    winding -= crossings[passed].1;
+                    passed += 1;
+                }
+                let p = Point2::new(x, y);
+                if let Some(clip) = clip {
+                    if !clip.contains(&p) {
+                        continue;
+                    }
+                }
+                if self.fill_rule.fills(count, winding) {
+                    f(p);
+                }
+            }
+        }
+    }
+}
+
+/*
+A Bezier curve drawn with a round pen: quadratic with 3 control points, cubic
+with 4. The pen is `width` wide at the start of the curve and narrows (or not)
+linearly to `taper` times that at the end. Edges are antialiased, so unlike
+the other shapes a stroke can cover pixels only partly.
+*/
+#[derive(Clone, PartialEq, Serialize)]
+pub struct Stroke {
+    controls: Vec<Point2<f32>>,
+    width: f32,
+    taper: f64,
+}
+
+impl Stroke {
+    /*
+    A random stroke of the given `degree` (2 for quadratic, 3 for cubic) with
+    its control points within `spread` of `center`. It's only tapered if
+    `config.stroke_taper` is set.
+    */
+    pub fn random_near(
+        config: &EvolutionConfig,
+        degree: usize,
+        center: Point2<f32>,
+        spread: (f64, f64),
+        width: u32,
+        height: u32,
+        rng: &mut Pcg32,
+    ) -> Stroke {
+        let controls = (0..degree + 1)
+            .map(|_| random_point_near(center, spread, rng))
+            .collect();
+        let max = (spread.0 * width as f64).min(spread.1 * height as f64) / scale(width, height);
+        let pen = random_length(max / 4.0, width, height, rng);
+        let taper = if config.stroke_taper {
+            rng.gen_range(0.0, 1.0)
+        } else {
+            1.0
+        };
+        Stroke {
+            controls,
+            width: pen,
+            taper,
+        }
+    }
+
+    // The point at `t` along the curve through `controls`, by de Casteljau's
+    // algorithm
+    fn point_at(controls: &[(f64, f64)], t: f64) -> (f64, f64) {
+        let mut points = controls.to_vec();
+        while points.len() > 1 {
+            for i in 0..points.len() - 1 {
+                let (a, b) = (points[i], points[i + 1]);
+                points[i] = (a.0 + t * (b.0 - a.0), a.1 + t * (b.1 - a.1));
+            }
+            points.pop();
+        }
+        points[0]
+    }
+
+    fn pixel_controls(&self, width: u32, height: u32) -> Vec<(f64, f64)> {
+        self.controls
+            .iter()
+            .map(|c| to_pixels(c, width, height))
+            .collect()
+    }
+
+    // The curve flattened into a polyline of (x, y, half the pen width), all
+    // in pixels, with about one segment per 2 pixels of control polygon
+    fn samples(&self, width: u32, height: u32) -> Vec<(f64, f64, f64)> {
+        let controls = self.pixel_controls(width, height);
+        let length: f64 = controls
+            .windows(2)
+            .map(|w| ((w[1].0 - w[0].0).powi(2) + (w[1].1 - w[0].1).powi(2)).sqrt())
+            .sum();
+        let segments = clamp((length / 2.0).ceil(), 4.0, 64.0) as usize;
+        let pen = self.width as f64 * scale(width, height);
+        (0..segments + 1)
+            .map(|i| {
+                let t = i as f64 / segments as f64;
+                let (x, y) = Stroke::point_at(&controls, t);
+                (x, y, pen / 2.0 * (1.0 - t * (1.0 - self.taper)))
+            })
+            .collect()
+    }
+
+    /*
+    How much of pixel `p` the stroke covers, from 0 to 1: how far inside the
+    nearest part of the pen's edge the pixel's center is, plus half a pixel.
+    Pixels more than half a pixel inside are fully covered; pixels straddling
+    the edge get a linear falloff.
+    */
+    fn coverage(samples: &[(f64, f64, f64)], p: &Point2<u32>) -> f64 {
+        let (px, py) = pixel_center(p);
+        let mut best = 0.0f64;
+        for w in samples.windows(2) {
+            let (a, b) = (w[0], w[1]);
+            let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+            let length_squared = dx * dx + dy * dy;
+            let t = if length_squared > 0.0 {
+                clamp(
+                    ((px - a.0) * dx + (py - a.1) * dy) / length_squared,
+                    0.0,
+                    1.0,
+                )
+            } else {
+                0.0
+            };
+            let (cx, cy) = (a.0 + t * dx - px, a.1 + t * dy - py);
+            let half_width = a.2 + t * (b.2 - a.2);
+            best = best.max(half_width + 0.5 - (cx * cx + cy * cy).sqrt());
+        }
+        best.min(1.0)
+    }
+}
+
+impl Shape for Stroke {
+    // The control points, then the width, then the taper if it's on
+    fn parts(&self, config: &EvolutionConfig) -> usize {
+        self.controls.len() + if config.stroke_taper { 2 } else { 1 }
+    }
+
+    fn jitter_part(
+        &mut self,
+        i: usize,
+        width: u32,
+        height: u32,
+        config: &EvolutionConfig,
+        rng: &mut Pcg32,
+    ) {
+        let n = self.controls.len();
+        if i < n {
+            jitter_point(&mut self.controls[i], config, rng);
+        } else if i == n {
+            jitter_length(&mut self.width, width, height, config, rng);
+        } else {
+            self.taper = perturb(
+                self.taper,
+                config.vertex_jitter,
+                0.0,
+                1.0,
+                config.mutation_distribution,
+                config.vertex_boundary,
+                rng,
+            );
+        }
+    }
+
+    fn translate(&mut self, _width: u32, _height: u32, config: &EvolutionConfig, rng: &mut Pcg32) {
+        translate_vertices(&mut self.controls, config, rng);
+    }
+
+    // The curve stays within its control points' box; the pen reaches past it
+    fn bbox(&self, width: u32, height: u32) -> BBox {
+        let reach = self.width as f64 * scale(width, height) / 2.0 + 1.0;
+        pixel_bbox(&self.pixel_controls(width, height), reach, width, height)
+    }
+
+    // Flattens the curve for each call, so anything testing many pixels
+    // should `rasterize` instead, which flattens it once
+    fn contains(&self, p: &Point2<u32>, width: u32, height: u32) -> bool {
+        self.bbox(width, height).contains(p)
+            && Stroke::coverage(&self.samples(width, height), p) > 0.0
+    }
+
+    fn center(&self) -> (f64, f64) {
+        let controls: Vec<(f64, f64)> = self
+            .controls
+            .iter()
+            .map(|c| (c.x as f64, c.y as f64))
+            .collect();
+        Stroke::point_at(&controls, 0.5)
+    }
+
+    fn rasterize(
+        &self,
+        width: u32,
+        height: u32,
+        clip: Option<&BBox>,
+        f: &mut dyn FnMut(Point2<u32>),
+    ) {
+        self.rasterize_coverage(width, height, clip, &mut |p, _| f(p));
+    }
+
+    fn rasterize_coverage(
+        &self,
+        width: u32,
+        height: u32,
+        clip: Option<&BBox>,
+        f: &mut dyn FnMut(Point2<u32>, f64),
+    ) {
+        let samples = self.samples(width, height);
+        for p in self.bbox(width, height) {
+            if let Some(clip) = clip {
+                if !clip.contains(&p) {
+                    continue;
+                }
+            }
+            let coverage = Stroke::coverage(&samples, &p);
+            if coverage > 0.0 {
+                f(p, coverage);
+            }
+        }
+    }
+}
+
+/*
+The shape of one gene, as one of the concrete kinds, so genes can be cloned,
+compared and serialized (which `Box<dyn Shape>` can't easily be). Use `shape()`
+and `shape_mut()` to work with it.
+*/
+#[derive(Clone, PartialEq, Serialize)]
+pub enum Geometry {
+    Triangle(Triangle),
+    Circle(Circle),
+    Ellipse(Ellipse),
+    RotatedEllipse(RotatedEllipse),
+    RotatedRect(RotatedRect),
+    Polygon(Polygon),
+    Stroke(Stroke),
+}
+
+// `Mixed` resolves to one of the others, uniformly
+fn resolve(kind: ShapeKind, rng: &mut Pcg32) -> ShapeKind {
+    if kind != ShapeKind::Mixed {
+        return kind;
+    }
+    match rng.gen_range(0, 8) {
+        0 => ShapeKind::Triangle,
+        1 => ShapeKind::Circle,
+        2 => ShapeKind::Ellipse,
+        3 => ShapeKind::RotatedEllipse,
+        4 => ShapeKind::RotatedRect,
+        5 => ShapeKind::Polygon,
+        6 => ShapeKind::QuadraticStroke,
+        _ => ShapeKind::CubicStroke,
+    }
+}
+
+impl Geometry {
+    // A random shape of `config.shape_kind`
+    pub fn random(config: &EvolutionConfig, width: u32, height: u32, rng: &mut Pcg32) -> Geometry {
+        let kind = resolve(config.shape_kind, rng);
+        match kind {
+            ShapeKind::Circle => Geometry::Circle(Circle::random(width, height, rng)),
+            ShapeKind::Ellipse => Geometry::Ellipse(Ellipse::random(width, height, rng)),
+            ShapeKind::RotatedEllipse => {
+                Geometry::RotatedEllipse(RotatedEllipse::random(width, height, rng))
+            }
+            ShapeKind::RotatedRect => {
+                Geometry::RotatedRect(RotatedRect::random(width, height, rng))
+            }
+            ShapeKind::Polygon | ShapeKind::QuadraticStroke | ShapeKind::CubicStroke => {
+                let center = random_point(rng);
+                Geometry::random_near_of(kind, config, center, (0.25, 0.25), width, height, rng)
+            }
+            ShapeKind::Triangle | ShapeKind::Mixed => {
+                Geometry::Triangle(Triangle::random(width, height, rng))
+            }
+        }
+    }
+
+    pub fn random_near(
+        config: &EvolutionConfig,
+        center: Point2<f32>,
+        spread: (f64, f64),
+        width: u32,
+        height: u32,
+        rng: &mut Pcg32,
+    ) -> Geometry {
+        let kind = resolve(config.shape_kind, rng);
+        Geometry::random_near_of(kind, config, center, spread, width, height, rng)
+    }
+
+    fn random_near_of(
+        kind: ShapeKind,
+        config: &EvolutionConfig,
+        center: Point2<f32>,
+        spread: (f64, f64),
+        width: u32,
+        height: u32,
+        rng: &mut Pcg32,
+    ) -> Geometry {
+        match kind {
+            ShapeKind::Circle => {
+                Geometry::Circle(Circle::random_near(center, spread, width, height, rng))
+            }
+            ShapeKind::Ellipse => {
+                Geometry::Ellipse(Ellipse::random_near(center, spread, width, height, rng))
+            }
+            ShapeKind::RotatedEllipse => Geometry::RotatedEllipse(RotatedEllipse::random_near(
+                center, spread, width, height, rng,
+            )),
+            ShapeKind::RotatedRect => {
+                Geometry::RotatedRect(RotatedRect::random_near(center, spread, width, height, rng))
+            }
+            ShapeKind::Polygon => {
+                Geometry::Polygon(Polygon::random_near(config, center, spread, rng))
+            }
+            ShapeKind::QuadraticStroke => Geometry::Stroke(Stroke::random_near(
+                config, 2, center, spread, width, height, rng,
+            )),
+            ShapeKind::CubicStroke => Geometry::Stroke(Stroke::random_near(
+                config, 3, center, spread, width, height, rng,
+            )),
+            ShapeKind::Triangle | ShapeKind::Mixed => {
+                Geometry::Triangle(Triangle::random_near(center, spread, width, height, rng))
+            }
+        }
+    }
+
+    pub fn shape(&self) -> &dyn Shape {
+        match self {
+            Geometry::Triangle(s) => s,
+            Geometry::Circle(s) => s,
+            Geometry::Ellipse(s) => s,
+            Geometry::RotatedEllipse(s) => s,
+            Geometry::RotatedRect(s) => s,
+            Geometry::Polygon(s) => s,
+            Geometry::Stroke(s) => s,
+        }
+    }
+
+    pub fn shape_mut(&mut self) -> &mut dyn Shape {
+        match self {
+            Geometry::Triangle(s) => s,
+            Geometry::Circle(s) => s,
+            Geometry::Ellipse(s) => s,
+            Geometry::RotatedEllipse(s) => s,
+            Geometry::RotatedRect(s) => s,
+            Geometry::Polygon(s) => s,
+            Geometry::Stroke(s) => s,
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use fitness::Reference;
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg32;
+use std::mem;
+use {entropy_rng, Color, ConfigError, EvolutionConfig, FitnessMetric, Gene, Pixel, RandomImage};
+
+/*
+The state `HillClimber` and `SimulatedAnnealing` share: one image being improved
+in place, plus what is needed to take back its last change cheaply. A step
+`propose`s a change and then either keeps it or `revert`s it; the drivers only
+differ in how they decide.
+*/
+pub struct SingleImage {
+    pub image: RandomImage,
+    pub reference: Reference,
+    pub config: EvolutionConfig,
+    pub rng: Pcg32,
+    // Pixels of the last accepted render, swapped back in on rejection so we
+    // don't have to re-render
+    accepted_pixels: Vec<Pixel>,
+    // Per-pixel errors of the current image, so a step only re-scores the
+    // pixels its mutation touched (see `Reference::evaluate_region`), and
+    // those of the last accepted one, swapped back like `accepted_pixels`
+    errors: Vec<f64>,
+    accepted_errors: Vec<f64>,
+    // What the image was before the last proposal
+    previous_genes: Vec<Gene>,
+    previous_background: Color,
+    previous_fitness: f64,
+}
+
+impl SingleImage {
+    pub fn new(
+        width: u32,
+        height: u32,
+        ref_values: Vec<u8>,
+        reference_w: u32,
+        reference_h: u32,
+        config: EvolutionConfig,
+    ) -> Result<SingleImage, ConfigError> {
+        config.validate()?;
+        let mut rng = entropy_rng();
+        let reference = Reference::new(ref_values, reference_w, reference_h);
+        let mut image =
+            RandomImage::new_from_reference(width, height, &reference, &config, &mut rng);
+        reference.evaluate(&mut image);
+        Ok(SingleImage {
+            accepted_pixels: image.pixels.clone(),
+            previous_genes: image.genes.clone(),
+            previous_background: image.background.clone(),
+            previous_fitness: image.fitness,
+            image,
+            reference,
+            config,
+            rng,
+            errors: vec![],
+            accepted_errors: vec![],
+        })
+    }
+
+    // Starts over from a fresh image
+    pub fn restart(&mut self) {
+        let (width, height) = (self.image.width, self.image.height);
+        self.image = RandomImage::new_from_reference(
+            width,
+            height,
+            &self.reference,
+            &self.config,
+            &mut self.rng,
+        );
+        self.reference.evaluate(&mut self.image);
+        self.errors.clear();
+    }
+
+    pub fn reseed(&mut self, seed: u64) {
+        self.rng = Pcg32::seed_from_u64(seed);
+        self.restart();
+    }
+
+    pub fn set_metric(&mut self, metric: Box<dyn FitnessMetric>) {
+        self.reference.set_metric(metric);
+        self.reevaluate();
+    }
+
+    // Re-scores the whole image, e.g. after its genes were replaced wholesale
+    pub fn reevaluate(&mut self) {
+        self.reference.evaluate(&mut self.image);
+        self.errors.clear();
+    }
+
+    /*
+    Makes one change with `config` (a color solve or a mutation, see
+    `EvolutionConfig::color_solve_rate`), re-scores only the pixels it touched,
+    and returns the new fitness. Call `revert` to take the change back.
+    */
+    pub fn propose(&mut self, config: &EvolutionConfig) -> f64 {
+        self.previous_fitness = self.image.fitness;
+        self.previous_genes.clone_from(&self.image.genes);
+        self.previous_background = self.image.background.clone();
+        self.accepted_pixels.copy_from_slice(&self.image.pixels);
+        self.accepted_errors.clone_from(&self.errors);
+
+        let guide = self.reference.guide(&self.image, config.error_bias);
+        if config.color_solve_rate > self.rng.gen::<f64>() {
+            self.image
+                .color_solve_step(&self.reference, guide.as_ref(), &mut self.rng);
+        } else {
+            self.image
+                .mutate_step(config, guide.as_ref(), &mut self.rng);
+        }
+        // Nothing to redraw, and `changed_region` would ask for everything
+        if self.image.genes == self.previous_genes
+            && self.image.background == self.previous_background
+        {
+            return self.image.fitness;
+        }
+        let region = self
+            .image
+            .changed_region(&self.previous_genes, &self.previous_background);
+        self.reference
+            .evaluate_region(&mut self.image, region.as_ref(), &mut self.errors)
+    }
+
+    // Puts back the image as it was before the last `propose`
+    pub fn revert(&mut self) {
+        mem::swap(&mut self.image.genes, &mut self.previous_genes);
+        self.image.background = self.previous_background.clone();
+        self.image.fitness = self.previous_fitness;
+        mem::swap(&mut self.image.pixels, &mut self.accepted_pixels);
+        mem::swap(&mut self.errors, &mut self.accepted_errors);
+    }
+}
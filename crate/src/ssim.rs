@@ -0,0 +1,383 @@
+use fitness::{mean, weighted_mean, FitnessMetric};
+use {ConfigError, Pixel};
+
+/*
+Structural similarity (SSIM) compares local means, variances and covariances
+over sliding `window` x `window` blocks instead of individual pixels, so it
+rewards getting edges and texture right rather than a blurry average.
+It's computed per RGB channel (alpha is ignored) and averaged.
+
+SSIM is 1 for identical images, so the error reported is 1 - SSIM, optionally
+blended with MSE (scaled to [0, 1]): `mse_weight` = 0 is pure SSIM, 1 pure MSE.
+`k1` and `k2` are the usual stabilizing constants (0.01 and 0.03).
+*/
+pub struct Ssim {
+    pub window: u32,
+    pub k1: f64,
+    pub k2: f64,
+    pub mse_weight: f64,
+}
+
+impl Default for Ssim {
+    fn default() -> Self {
+        Self {
+            window: 7,
+            k1: 0.01,
+            k2: 0.03,
+            mse_weight: 0.0,
+        }
+    }
+}
+
+// The checks shared by `Ssim::new` and `MsSsim::new`
+fn check_parameters(window: u32, k1: f64, k2: f64, mse_weight: f64) -> Result<(), ConfigError> {
+    if window == 0 {
+        return Err(ConfigError::ZeroSsimWindow);
+    }
+    if !k1.is_finite() || !k2.is_finite() {
+        return Err(ConfigError::SsimConstants(k1, k2));
+    }
+    if !(0.0..=1.0).contains(&mse_weight) {
+        return Err(ConfigError::MseWeight(mse_weight));
+    }
+    Ok(())
+}
+
+impl Ssim {
+    pub fn new(window: u32, k1: f64, k2: f64, mse_weight: f64) -> Result<Ssim, ConfigError> {
+        check_parameters(window, k1, k2, mse_weight)?;
+        Ok(Ssim {
+            window,
+            k1,
+            k2,
+            mse_weight,
+        })
+    }
+
+    fn ssim_error(
+        &self,
+        pixels: &[Pixel],
+        reference: &[u8],
+        weights: Option<&[f64]>,
+        width: u32,
+        height: u32,
+    ) -> f64 {
+        let (xs, ys) = channel_planes(pixels, reference);
+        let mut ssim = 0.0;
+        for c in 0..3 {
+            let stats = window_stats(
+                &xs[c],
+                &ys[c],
+                weights,
+                width,
+                height,
+                self.window,
+                (self.k1, self.k2),
+            );
+            ssim += stats.ssim;
+        }
+        blend(1.0 - ssim / 3.0, &xs, &ys, weights, self.mse_weight)
+    }
+}
+
+impl FitnessMetric for Ssim {
+    fn error(&self, pixels: &[Pixel], reference: &[u8], width: u32, height: u32) -> f64 {
+        self.ssim_error(pixels, reference, None, width, height)
+    }
+
+    // Each window's SSIM counts in proportion to the total weight under it
+    fn weighted_error(
+        &self,
+        pixels: &[Pixel],
+        reference: &[u8],
+        weights: &[f64],
+        width: u32,
+        height: u32,
+    ) -> f64 {
+        self.ssim_error(pixels, reference, Some(weights), width, height)
+    }
+}
+
+/*
+Multi-scale SSIM: contrast/structure are compared at `scales` successively
+halved resolutions, and luminance at the coarsest one, then combined with the
+standard per-scale weights. This judges structure at several sizes at once.
+Scales that would shrink the image below the window size are skipped.
+*/
+pub struct MsSsim {
+    pub window: u32,
+    pub k1: f64,
+    pub k2: f64,
+    pub scales: u32,
+    pub mse_weight: f64,
+}
+
+impl Default for MsSsim {
+    fn default() -> Self {
+        Self {
+            window: 7,
+            k1: 0.01,
+            k2: 0.03,
+            scales: 5,
+            mse_weight: 0.0,
+        }
+    }
+}
+
+// From Wang, Simoncelli & Bovik, "Multi-scale structural similarity for image
+// quality assessment" (2003)
+const MS_SSIM_WEIGHTS: [f64; 5] = [0.0448, 0.2856, 0.3001, 0.2363, 0.1333];
+
+impl MsSsim {
+    pub fn new(
+        window: u32,
+        k1: f64,
+        k2: f64,
+        scales: u32,
+        mse_weight: f64,
+    ) -> Result<MsSsim, ConfigError> {
+        check_parameters(window, k1, k2, mse_weight)?;
+        if scales == 0 {
+            return Err(ConfigError::ZeroSsimScales);
+        }
+        Ok(MsSsim {
+            window,
+            k1,
+            k2,
+            scales,
+            mse_weight,
+        })
+    }
+
+    fn ms_ssim_error(
+        &self,
+        pixels: &[Pixel],
+        reference: &[u8],
+        weights: Option<&[f64]>,
+        width: u32,
+        height: u32,
+    ) -> f64 {
+        let (xs, ys) = channel_planes(pixels, reference);
+
+        let mut scales = 1;
+        let (mut w, mut h) = (width / 2, height / 2);
+        while scales < self.scales.min(MS_SSIM_WEIGHTS.len() as u32)
+            && w >= self.window
+            && h >= self.window
+        {
+            scales += 1;
+            w /= 2;
+            h /= 2;
+        }
+        let scale_weights = &MS_SSIM_WEIGHTS[..scales as usize];
+        let total_weight: f64 = scale_weights.iter().sum();
+
+        let mut ms_ssim = 0.0;
+        for c in 0..3 {
+            let (mut x, mut y) = (xs[c].clone(), ys[c].clone());
+            let mut pixel_weights = weights.map(|w| w.to_vec());
+            let (mut w, mut h) = (width, height);
+            let mut value = 1.0;
+            for (scale, weight) in scale_weights.iter().enumerate() {
+                let stats = window_stats(
+                    &x,
+                    &y,
+                    pixel_weights.as_ref().map(|w| &w[..]),
+                    w,
+                    h,
+                    self.window,
+                    (self.k1, self.k2),
+                );
+                let weight = weight / total_weight;
+                value *= stats.cs.max(0.0).powf(weight);
+                if scale + 1 == scale_weights.len() {
+                    value *= stats.luminance.max(0.0).powf(weight);
+                } else {
+                    x = downsample(&x, w, h);
+                    y = downsample(&y, w, h);
+                    pixel_weights = pixel_weights.map(|p| downsample(&p, w, h));
+                    w /= 2;
+                    h /= 2;
+                }
+            }
+            ms_ssim += value;
+        }
+        blend(1.0 - ms_ssim / 3.0, &xs, &ys, weights, self.mse_weight)
+    }
+}
+
+impl FitnessMetric for MsSsim {
+    fn error(&self, pixels: &[Pixel], reference: &[u8], width: u32, height: u32) -> f64 {
+        self.ms_ssim_error(pixels, reference, None, width, height)
+    }
+
+    // Weights are averaged down along with the image at each scale
+    fn weighted_error(
+        &self,
+        pixels: &[Pixel],
+        reference: &[u8],
+        weights: &[f64],
+        width: u32,
+        height: u32,
+    ) -> f64 {
+        self.ms_ssim_error(pixels, reference, Some(weights), width, height)
+    }
+}
+
+fn blend(
+    ssim_error: f64,
+    xs: &[Vec<f64>; 3],
+    ys: &[Vec<f64>; 3],
+    weights: Option<&[f64]>,
+    mse_weight: f64,
+) -> f64 {
+    if mse_weight <= 0.0 {
+        return ssim_error;
+    }
+    let errors: Vec<f64> = (0..xs[0].len())
+        .map(|i| (0..3).map(|c| (xs[c][i] - ys[c][i]).powi(2)).sum::<f64>() / 3.0)
+        .collect();
+    let mse = match weights {
+        Some(weights) => weighted_mean(&errors, weights),
+        None => mean(&errors),
+    } / (255.0 * 255.0);
+    (1.0 - mse_weight) * ssim_error + mse_weight * mse
+}
+
+// Splits the image and reference into separate R, G and B planes
+fn channel_planes(pixels: &[Pixel], reference: &[u8]) -> ([Vec<f64>; 3], [Vec<f64>; 3]) {
+    if pixels.len() * 4 != reference.len() {
+        panic!(
+            "Got bad sizes for compare: {} pixels vs {} reference values",
+            pixels.len(),
+            reference.len()
+        );
+    }
+    let mut xs = [vec![], vec![], vec![]];
+    let mut ys = [vec![], vec![], vec![]];
+    for (pixel, values) in pixels.iter().zip(reference.chunks(4)) {
+        let channels = [pixel.r, pixel.g, pixel.b];
+        for c in 0..3 {
+            xs[c].push(channels[c] as f64);
+            ys[c].push(values[c] as f64);
+        }
+    }
+    (xs, ys)
+}
+
+// Halves a plane in each dimension by averaging 2x2 blocks
+fn downsample(plane: &[f64], width: u32, height: u32) -> Vec<f64> {
+    let (w, h) = ((width / 2) as usize, (height / 2) as usize);
+    let width = width as usize;
+    let mut out = Vec::with_capacity(w * h);
+    for y in 0..h {
+        for x in 0..w {
+            let i = 2 * y * width + 2 * x;
+            out.push((plane[i] + plane[i + 1] + plane[i + width] + plane[i + width + 1]) / 4.0);
+        }
+    }
+    out
+}
+
+struct WindowStats {
+    ssim: f64,
+    // contrast * structure, and luminance, terms of SSIM
+    cs: f64,
+    luminance: f64,
+}
+
+/*
+Mean SSIM terms over every window position, or, given per-pixel `weights`, the
+mean weighted by the total weight in each window. Window sums come from
+summed-area tables, so the cost doesn't depend on the window size.
+*/
+fn window_stats(
+    x: &[f64],
+    y: &[f64],
+    weights: Option<&[f64]>,
+    width: u32,
+    height: u32,
+    window: u32,
+    (k1, k2): (f64, f64),
+) -> WindowStats {
+    // An empty image has no windows, and nothing in it differs
+    if width == 0 || height == 0 {
+        return WindowStats {
+            ssim: 1.0,
+            cs: 1.0,
+            luminance: 1.0,
+        };
+    }
+    let c1 = (k1 * 255.0).powi(2);
+    let c2 = (k2 * 255.0).powi(2);
+    let window = window.min(width).min(height).max(1) as usize;
+    let (width, height) = (width as usize, height as usize);
+
+    // Each table is (width + 1) x (height + 1), with a leading row/column of
+    // zeros. The last entry sums the pixel weights.
+    let stride = width + 1;
+    let mut sums = vec![[0.0; 6]; stride * (height + 1)];
+    for row in 0..height {
+        let mut row_sum = [0.0; 6];
+        for col in 0..width {
+            let i = row * width + col;
+            let (a, b) = (x[i], y[i]);
+            let weight = weights.map_or(1.0, |w| w[i]);
+            let v = [a, b, a * a, b * b, a * b, weight];
+            for k in 0..6 {
+                row_sum[k] += v[k];
+                sums[(row + 1) * stride + col + 1][k] =
+                    sums[row * stride + col + 1][k] + row_sum[k];
+            }
+        }
+    }
+
+    let n = (window * window) as f64;
+    let mut total = WindowStats {
+        ssim: 0.0,
+        cs: 0.0,
+        luminance: 0.0,
+    };
+    let mut total_weight = 0.0;
+    for top in 0..=(height - window) {
+        for left in 0..=(width - window) {
+            let (bottom, right) = (top + window, left + window);
+            let mut s = [0.0; 6];
+            for k in 0..6 {
+                s[k] = sums[bottom * stride + right][k]
+                    - sums[top * stride + right][k]
+                    - sums[bottom * stride + left][k]
+                    + sums[top * stride + left][k];
+            }
+            let (mx, my) = (s[0] / n, s[1] / n);
+            let var_x = (s[2] / n - mx * mx).max(0.0);
+            let var_y = (s[3] / n - my * my).max(0.0);
+            let cov = s[4] / n - mx * my;
+            let weight = if weights.is_some() { s[5] } else { 1.0 };
+
+            let luminance = (2.0 * mx * my + c1) / (mx * mx + my * my + c1);
+            let cs = (2.0 * cov + c2) / (var_x + var_y + c2);
+            total.ssim += weight * luminance * cs;
+            total.cs += weight * cs;
+            total.luminance += weight * luminance;
+            total_weight += weight;
+        }
+    }
+
+    if total_weight.is_nan() || total_weight <= 0.0 {
+        return window_stats(
+            x,
+            y,
+            None,
+            width as u32,
+            height as u32,
+            window as u32,
+            (k1, k2),
+        );
+    }
+    WindowStats {
+        ssim: total.ssim / total_weight,
+        cs: total.cs / total_weight,
+        luminance: total.luminance / total_weight,
+    }
+}
@@ -2,7 +2,29 @@ extern crate wasm_bindgen_test;
 use wasm_bindgen_test::*;
 
 extern crate image_evol;
-use image_evol::RandomImage;
+use image_evol::{
+  apply_boundary, random_step, tournament_select, AdaptiveCooling, BBox, BackgroundMode, BoundaryMode, Circle, ConfigError,
+  CoolingSchedule, CrossoverType, ErrorGuide, EvolutionConfig, ExponentialCooling, FillRule, FitnessMetric, FitnessMetricKind,
+  GradientKind, HillClimber, InitStrategy, Lab, LinearCooling, MsSsim, MutationDistribution, MutationMode, Pixel,
+  Polygon, Population, RandomImage, RotatedRect, Shape, ShapeKind, SimulatedAnnealing, Ssim, Triangle, WeightedMse,
+};
+
+extern crate nalgebra;
+use nalgebra::Point2;
+use std::collections::BTreeSet;
+
+extern crate rand;
+extern crate rand_pcg;
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg32;
+
+extern crate serde_json;
+use serde_json::Value;
+
+fn genes_of(genome: &str) -> Vec<Value> {
+  let genome: Value = serde_json::from_str(genome).unwrap();
+  genome["genes"].as_array().unwrap().clone()
+}
 
 #[wasm_bindgen_test]
 fn test_image() {
@@ -12,3 +34,1462 @@ fn test_image() {
   let p = i.get_pixel(0, 0);
   assert_eq!(p.r, 0);
 }
+
+fn gradient_reference(w: u32, h: u32) -> Vec<u8> {
+  let mut values = vec![];
+  for y in 0..h {
+    for x in 0..w {
+      values.extend_from_slice(&[(x * 255 / w) as u8, (y * 255 / h) as u8, 128, 255]);
+    }
+  }
+  values
+}
+
+#[wasm_bindgen_test]
+fn test_seeded_populations_match() {
+  let run = |seed: u64| {
+    let config = EvolutionConfig::default();
+    let mut pop = Population::with_config(60, 60, gradient_reference(20, 20), 20, 20, config).unwrap();
+    pop.reseed(seed);
+    for _ in 0..4 {
+      pop.add_member();
+    }
+    for _ in 0..3 {
+      pop.evolve();
+    }
+    (pop.best_fitness(), pop.best_genome_json())
+  };
+
+  assert_eq!(run(7), run(7));
+  assert_ne!(run(7).1, run(8).1);
+}
+
+#[wasm_bindgen_test]
+fn test_config_validation() {
+  let d = EvolutionConfig::default();
+  assert_eq!(d.validate(), Ok(()));
+  let nan = f64::NAN;
+  let cases = vec![
+    (EvolutionConfig { mutation_rate: 1.5, ..d }, ConfigError::MutationRate(1.5)),
+    (EvolutionConfig { cull_fraction: 1.0, ..d }, ConfigError::CullFraction(1.0)),
+    (EvolutionConfig { gene_count: 0, ..d }, ConfigError::ZeroGenes),
+    (EvolutionConfig { vertex_jitter: -0.1, ..d }, ConfigError::VertexJitter(-0.1)),
+    (EvolutionConfig { color_jitter: 300.0, ..d }, ConfigError::ColorJitter(300.0)),
+    (EvolutionConfig { tournament_size: 0, ..d }, ConfigError::ZeroTournamentSize),
+    (EvolutionConfig { crossover_rate: 2.0, ..d }, ConfigError::CrossoverRate(2.0)),
+    (EvolutionConfig { add_gene_rate: -1.0, ..d }, ConfigError::StructuralRate("add_gene_rate", -1.0)),
+    (EvolutionConfig { remove_gene_rate: 1.1, ..d }, ConfigError::StructuralRate("remove_gene_rate", 1.1)),
+    (EvolutionConfig { duplicate_gene_rate: 1.1, ..d }, ConfigError::StructuralRate("duplicate_gene_rate", 1.1)),
+    (EvolutionConfig { swap_gene_rate: 1.1, ..d }, ConfigError::StructuralRate("swap_gene_rate", 1.1)),
+    (EvolutionConfig { move_gene_rate: 1.1, ..d }, ConfigError::StructuralRate("move_gene_rate", 1.1)),
+    (EvolutionConfig { add_vertex_rate: 1.1, ..d }, ConfigError::StructuralRate("add_vertex_rate", 1.1)),
+    (EvolutionConfig { remove_vertex_rate: 1.1, ..d }, ConfigError::StructuralRate("remove_vertex_rate", 1.1)),
+    (EvolutionConfig { min_genes: 0, ..d }, ConfigError::GeneBounds { min: 0, max: 200, gene_count: 50 }),
+    (EvolutionConfig { max_genes: 10, ..d }, ConfigError::GeneBounds { min: 1, max: 10, gene_count: 50 }),
+    (EvolutionConfig { vertex_weight: -1.0, ..d }, ConfigError::ComponentWeights),
+    (
+      EvolutionConfig { vertex_weight: 0.0, channel_weight: 0.0, alpha_weight: 0.0, translate_weight: 0.0, ..d },
+      ConfigError::ComponentWeights,
+    ),
+    (EvolutionConfig { adaptation_interval: 0, ..d }, ConfigError::ZeroAdaptationInterval),
+    (EvolutionConfig { adaptation_factor: 1.0, ..d }, ConfigError::AdaptationFactor(1.0)),
+    (EvolutionConfig { min_step_scale: 0.0, ..d }, ConfigError::StepScaleBounds { min: 0.0, max: 10.0 }),
+    (EvolutionConfig { max_step_scale: 0.5, ..d }, ConfigError::StepScaleBounds { min: 0.01, max: 0.5 }),
+    (EvolutionConfig { pyramid_patience: 0, ..d }, ConfigError::ZeroPyramidPatience),
+    (EvolutionConfig { pyramid_min_improvement: 1.0, ..d }, ConfigError::PyramidMinImprovement(1.0)),
+    (EvolutionConfig { error_bias: 1.5, ..d }, ConfigError::ErrorBias(1.5)),
+    (EvolutionConfig { color_solve_rate: 1.5, ..d }, ConfigError::ColorSolveRate(1.5)),
+    (EvolutionConfig { background_color: 0x1000000, ..d }, ConfigError::BackgroundColor(0x1000000)),
+    (EvolutionConfig { background_mutation_rate: 1.5, ..d }, ConfigError::BackgroundMutationRate(1.5)),
+    (EvolutionConfig { max_polygon_vertices: 2, ..d }, ConfigError::MaxPolygonVertices(2)),
+  ];
+  for (config, error) in cases {
+    assert_eq!(config.validate(), Err(error));
+  }
+
+  // NaN is never in range (and never equal to itself, so match on the variant)
+  match (EvolutionConfig { mutation_rate: nan, ..d }).validate() {
+    Err(ConfigError::MutationRate(v)) => assert!(v.is_nan()),
+    other => panic!("expected MutationRate, got {:?}", other),
+  }
+  assert_eq!((EvolutionConfig { alpha_weight: nan, ..d }).validate(), Err(ConfigError::ComponentWeights));
+
+  // Omitted fields take their defaults; bad JSON and bad values are rejected
+  assert_eq!(EvolutionConfig::parse("{}"), Ok(d));
+  assert_eq!(
+    EvolutionConfig::parse(r#"{ "gene_count": 20, "crossover_type": "TwoPoint" }"#),
+    Ok(EvolutionConfig { gene_count: 20, crossover_type: CrossoverType::TwoPoint, ..d })
+  );
+  assert_eq!(EvolutionConfig::parse(r#"{ "gene_count": 0 }"#), Err(ConfigError::ZeroGenes));
+  match EvolutionConfig::parse(r#"{ "gene_count": "many" }"#) {
+    Err(ConfigError::Json(_)) => {}
+    other => panic!("expected a JSON error, got {:?}", other),
+  }
+}
+
+#[wasm_bindgen_test]
+fn test_tournament_selection_pressure() {
+  let reference = gradient_reference(10, 10);
+  let mut members: Vec<RandomImage> = (0..5)
+    .map(|seed| {
+      let mut image = RandomImage::new_with_rng(30, 30, &EvolutionConfig::default(), &mut Pcg32::seed_from_u64(seed));
+      image.calculate_fitness(&reference, 10, 10);
+      image
+    })
+    .collect();
+  members.sort();
+  assert!(members[0] < members[4]);
+
+  let best_share = |tournament_size: u32| {
+    let mut rng = Pcg32::seed_from_u64(1);
+    let wins = (0..2000).filter(|_| tournament_select(&members, tournament_size, &mut rng) == 0).count();
+    wins as f64 / 2000.0
+  };
+  // One entrant is a uniform pick; with four, the best wins 1 - (4/5)^4 of the time
+  assert!((best_share(1) - 0.2).abs() < 0.05);
+  assert!((best_share(4) - 0.59).abs() < 0.05);
+  assert_eq!(best_share(200), 1.0);
+}
+
+#[wasm_bindgen_test]
+fn test_crossover_children() {
+  let reference = gradient_reference(10, 10);
+  let config = EvolutionConfig::default();
+  let mut rng = Pcg32::seed_from_u64(2);
+  let mut long = RandomImage::new_with_rng(30, 30, &EvolutionConfig { gene_count: 8, ..config }, &mut rng);
+  let mut short = RandomImage::new_with_rng(30, 30, &EvolutionConfig { gene_count: 5, ..config }, &mut rng);
+  long.calculate_fitness(&reference, 10, 10);
+  short.calculate_fitness(&reference, 10, 10);
+  let long_genes = genes_of(&long.genome_json());
+  let short_genes = genes_of(&short.genome_json());
+
+  for &crossover in [CrossoverType::Uniform, CrossoverType::OnePoint, CrossoverType::TwoPoint].iter() {
+    for &(first, second, first_genes, second_genes) in
+      [(&long, &short, &long_genes, &short_genes), (&short, &long, &short_genes, &long_genes)].iter()
+    {
+      for _ in 0..50 {
+        let mut child = first.clone();
+        child.breed_with_rng(second, crossover, &mut rng);
+        let genes = genes_of(&child.genome_json());
+        // The child's length is between its parents'
+        assert!(genes.len() >= 5 && genes.len() <= 8);
+        match crossover {
+          // Where both parents have a gene each position takes one of them;
+          // past the shorter parent the genes are a subsequence of the longer's tail
+          CrossoverType::Uniform => {
+            for (i, gene) in genes.iter().take(5).enumerate() {
+              assert!(&first_genes[i] == gene || &second_genes[i] == gene);
+            }
+            let mut tail = long_genes[5..].iter();
+            assert!(genes[5..].iter().all(|gene| tail.any(|g| g == gene)));
+          }
+          // A head of the first parent, then the rest from the second
+          CrossoverType::OnePoint => {
+            let head = genes.iter().zip(first_genes.iter()).take_while(|(a, b)| a == b).count();
+            let tail = &genes[head..];
+            assert_eq!(tail, &second_genes[second_genes.len() - tail.len()..]);
+          }
+          // The first parent, with a run of the second's genes in the middle
+          CrossoverType::TwoPoint => {
+            let head = genes.iter().zip(first_genes.iter()).take_while(|(a, b)| a == b).count();
+            let tail = genes[head..]
+              .iter()
+              .rev()
+              .zip(first_genes[head..].iter().rev())
+              .take_while(|(a, b)| a == b)
+              .count();
+            let middle = &genes[head..genes.len() - tail];
+            assert!(middle.is_empty() || second_genes.windows(middle.len()).any(|w| w == middle));
+          }
+        }
+      }
+    }
+  }
+}
+
+// The default config with every structural and background mutation off and
+// no self-adapted step sizes, so a mutation only changes genes as
+// `mutation_mode` says
+fn unstructured_config() -> EvolutionConfig {
+  EvolutionConfig {
+    self_adaptive: false,
+    background_mutation_rate: 0.0,
+    add_gene_rate: 0.0,
+    remove_gene_rate: 0.0,
+    duplicate_gene_rate: 0.0,
+    swap_gene_rate: 0.0,
+    move_gene_rate: 0.0,
+    add_vertex_rate: 0.0,
+    remove_vertex_rate: 0.0,
+    ..EvolutionConfig::default()
+  }
+}
+
+#[wasm_bindgen_test]
+fn test_structural_mutations() {
+  // With no jitter and every other structural rate off, a mutation makes
+  // exactly the one structural change being tested
+  let still = EvolutionConfig {
+    gene_count: 6,
+    vertex_jitter: 0.0,
+    color_jitter: 0.0,
+    ..unstructured_config()
+  };
+  let is_subsequence = |short: &[Value], long: &[Value]| {
+    let mut long = long.iter();
+    short.iter().all(|gene| long.any(|g| g == gene))
+  };
+  let sorted = |genes: &[Value]| {
+    let mut genes: Vec<String> = genes.iter().map(|g| g.to_string()).collect();
+    genes.sort();
+    genes
+  };
+  let mut rng = Pcg32::seed_from_u64(4);
+  for _ in 0..20 {
+    let image = RandomImage::new_with_rng(30, 30, &still, &mut rng);
+    let before = genes_of(&image.genome_json());
+    let mutated = |config: EvolutionConfig, rng: &mut Pcg32| {
+      let mut image = image.clone();
+      image.mutate_with_rng(&config, rng);
+      genes_of(&image.genome_json())
+    };
+
+    let added = mutated(EvolutionConfig { add_gene_rate: 1.0, ..still }, &mut rng);
+    assert_eq!(added.len(), 7);
+    assert!(is_subsequence(&before, &added));
+
+    let removed = mutated(EvolutionConfig { remove_gene_rate: 1.0, ..still }, &mut rng);
+    assert_eq!(removed.len(), 5);
+    assert!(is_subsequence(&removed, &before));
+
+    // The copy lands just above its original
+    let duplicated = mutated(EvolutionConfig { duplicate_gene_rate: 1.0, ..still }, &mut rng);
+    assert_eq!(duplicated.len(), 7);
+    let copy = (1..7).find(|&i| duplicated[i] == duplicated[i - 1]).unwrap();
+    let mut deduplicated = duplicated.clone();
+    deduplicated.remove(copy);
+    assert_eq!(deduplicated, before);
+
+    let swapped = mutated(EvolutionConfig { swap_gene_rate: 1.0, ..still }, &mut rng);
+    let moved_places: Vec<usize> = (0..6).filter(|&i| swapped[i] != before[i]).collect();
+    assert_eq!(moved_places.len(), 2);
+    assert_eq!(swapped[moved_places[0]], before[moved_places[1]]);
+    assert_eq!(swapped[moved_places[1]], before[moved_places[0]]);
+
+    // Taking the moved gene back out leaves the others in their old order
+    let moved = mutated(EvolutionConfig { move_gene_rate: 1.0, ..still }, &mut rng);
+    assert_ne!(moved, before);
+    assert_eq!(sorted(&moved), sorted(&before));
+    assert!((0..6).any(|i| {
+      let mut rest = moved.clone();
+      let gene = rest.remove(i);
+      let j = before.iter().position(|g| *g == gene).unwrap();
+      let mut others = before.clone();
+      others.remove(j);
+      rest == others
+    }));
+
+    // Gene count bounds are respected
+    let full = mutated(EvolutionConfig { add_gene_rate: 1.0, duplicate_gene_rate: 1.0, max_genes: 6, ..still }, &mut rng);
+    assert_eq!(full, before);
+    let minimal = mutated(EvolutionConfig { remove_gene_rate: 1.0, min_genes: 6, ..still }, &mut rng);
+    assert_eq!(minimal, before);
+  }
+}
+
+#[wasm_bindgen_test]
+fn test_component_weights() {
+  let component = EvolutionConfig {
+    mutation_mode: MutationMode::Component,
+    color_jitter: 50.0,
+    color_boundary: BoundaryMode::Reflect,
+    alpha_boundary: BoundaryMode::Reflect,
+    vertex_weight: 0.0,
+    channel_weight: 0.0,
+    alpha_weight: 0.0,
+    translate_weight: 0.0,
+    ..unstructured_config()
+  };
+  // Whether one mutation moved some gene's shape, and whether it recolored one
+  let changes = |config: EvolutionConfig, rng: &mut Pcg32| {
+    let mut image = RandomImage::new_with_rng(30, 30, &config, rng);
+    let before = genes_of(&image.genome_json());
+    image.mutate_with_rng(&config, rng);
+    let after = genes_of(&image.genome_json());
+    let moved = before.iter().zip(after.iter()).any(|(b, a)| b["geometry"] != a["geometry"]);
+    let recolored = |channels: &[&str]| {
+      before.iter().zip(after.iter()).any(|(b, a)| channels.iter().any(|&c| b["color"][c] != a["color"][c]))
+    };
+    (moved, recolored(&["r", "g", "b"]), recolored(&["a"]))
+  };
+
+  let mut rng = Pcg32::seed_from_u64(9);
+  for _ in 0..100 {
+    let vertex = EvolutionConfig { vertex_weight: 1.0, ..component };
+    assert_eq!(changes(vertex, &mut rng), (true, false, false));
+    let translate = EvolutionConfig { translate_weight: 1.0, ..component };
+    assert_eq!(changes(translate, &mut rng), (true, false, false));
+    // A color step can round back to the same value
+    let (moved, _, faded) = changes(EvolutionConfig { channel_weight: 1.0, ..component }, &mut rng);
+    assert!(!moved && !faded);
+    let (moved, recolored, _) = changes(EvolutionConfig { alpha_weight: 1.0, ..component }, &mut rng);
+    assert!(!moved && !recolored);
+  }
+
+  // Component types are picked in proportion to their weights
+  let weighted = EvolutionConfig { vertex_weight: 3.0, channel_weight: 1.0, ..component };
+  let (mut moved, mut recolored) = (0, 0);
+  for _ in 0..2000 {
+    let (m, r, _) = changes(weighted, &mut rng);
+    assert!(!(m && r));
+    moved += m as u32;
+    recolored += r as u32;
+  }
+  let ratio = moved as f64 / recolored as f64;
+  assert!(ratio > 2.5 && ratio < 3.5, "ratio {}", ratio);
+}
+
+#[wasm_bindgen_test]
+fn test_crossover_respects_max_genes() {
+  let config = EvolutionConfig { gene_count: 20, ..EvolutionConfig::default() };
+  let mut pop = Population::with_config(30, 30, gradient_reference(10, 10), 10, 10, config).unwrap();
+  pop.reseed(8);
+  for _ in 0..8 {
+    pop.add_member();
+  }
+
+  // Every member was made with 20 genes; only the offspring bred after the
+  // bound was lowered are held to it
+  pop
+    .set_config(EvolutionConfig {
+      gene_count: 10,
+      max_genes: 10,
+      cull_fraction: 0.5,
+      crossover_rate: 1.0,
+      mutation_rate: 0.0,
+      ..config
+    })
+    .unwrap();
+  pop.evolve();
+  let mut lengths: Vec<usize> = pop.members().iter().map(|m| genes_of(&m.genome_json()).len()).collect();
+  lengths.sort();
+  assert_eq!(lengths, vec![10, 10, 10, 10, 20, 20, 20, 20]);
+}
+
+#[wasm_bindgen_test]
+fn test_hill_climber_never_regresses() {
+  for &mutation_mode in [MutationMode::All, MutationMode::Gene].iter() {
+    let config = EvolutionConfig { mutation_mode, ..EvolutionConfig::default() };
+    let mut climber = HillClimber::with_config(60, 60, gradient_reference(20, 20), 20, 20, config).unwrap();
+    climber.reseed(3);
+
+    let mut fitness = climber.fitness();
+    for _ in 0..20 {
+      climber.step();
+      assert!(climber.fitness() <= fitness);
+      fitness = climber.fitness();
+    }
+    assert_eq!(climber.steps(), 20);
+  }
+}
+
+#[wasm_bindgen_test]
+fn test_single_image_steps_change_one_gene() {
+  // The default `MutationMode::All` moves one gene per step here, not all of
+  // them. Kept hot, the annealer keeps every step, so each one shows.
+  assert_eq!(EvolutionConfig::default().mutation_mode, MutationMode::All);
+  let mut annealing = SimulatedAnnealing::with_config(60, 60, gradient_reference(20, 20), 20, 20, unstructured_config()).unwrap();
+  annealing.reseed(5, 1e9);
+  annealing.set_exponential_cooling(1.0);
+  for _ in 0..20 {
+    let before = genes_of(&annealing.image().genome_json());
+    assert!(annealing.step().accepted);
+    let after = genes_of(&annealing.image().genome_json());
+    assert_eq!(before.iter().zip(after.iter()).filter(|(b, a)| b != a).count(), 1);
+  }
+
+  let mut climber = HillClimber::with_config(60, 60, gradient_reference(20, 20), 20, 20, unstructured_config()).unwrap();
+  climber.reseed(5);
+  for _ in 0..20 {
+    let before = genes_of(&climber.genome_json());
+    if climber.step() {
+      let after = genes_of(&climber.genome_json());
+      assert_eq!(before.iter().zip(after.iter()).filter(|(b, a)| b != a).count(), 1);
+    }
+  }
+  assert!(climber.accepted() > 0);
+}
+
+#[wasm_bindgen_test]
+fn test_annealing_acceptance_and_schedules() {
+  let e = ::std::f64::consts::E;
+  let cases = [
+    (-1.0, 1.0, 1.0),
+    (-1.0, 0.0, 1.0),
+    (0.0, 1.0, 1.0),
+    (1.0, 1.0, 1.0 / e),
+    (2.0, 0.5, 1.0 / e.powi(4)),
+    (1.0, 0.0, 0.0),
+  ];
+  for &(delta, temperature, probability) in cases.iter() {
+    assert!((SimulatedAnnealing::acceptance_probability(delta, temperature) - probability).abs() < 1e-12);
+  }
+
+  assert_eq!(LinearCooling { decrement: 0.25 }.next_temperature(1.0, 1, 0.5), 0.75);
+  assert_eq!(ExponentialCooling { alpha: 0.5 }.next_temperature(1.0, 1, 0.5), 0.5);
+  let mut adaptive = AdaptiveCooling { target_acceptance: 0.3, factor: 0.5 };
+  assert_eq!(adaptive.next_temperature(1.0, 1, 0.5), 0.5);
+  assert_eq!(adaptive.next_temperature(1.0, 1, 0.1), 2.0);
+
+  // The driver applies its schedule every step, never going below the minimum
+  let config = EvolutionConfig::default();
+  let mut annealing = SimulatedAnnealing::with_config(60, 60, gradient_reference(20, 20), 20, 20, config).unwrap();
+  annealing.reseed(5, 1.0);
+  annealing.set_linear_cooling(0.25);
+  annealing.set_min_temperature(0.2);
+  let temperatures: Vec<f64> = (0..5).map(|_| annealing.step().temperature).collect();
+  assert_eq!(temperatures, vec![0.75, 0.5, 0.25, 0.2, 0.2]);
+
+  annealing.set_exponential_cooling(0.5);
+  annealing.set_temperature(1.0);
+  assert_eq!(annealing.step().temperature, 0.5);
+
+  annealing.set_adaptive_cooling(2.0, 0.5);
+  annealing.set_temperature(1.0);
+  assert_eq!(annealing.step().temperature, 2.0);
+}
+
+#[wasm_bindgen_test]
+fn test_annealing_reheat_and_restore_best() {
+  let config = EvolutionConfig::default();
+  let mut annealing = SimulatedAnnealing::with_config(60, 60, gradient_reference(20, 20), 20, 20, config).unwrap();
+  annealing.reseed(6, 0.0);
+  annealing.set_min_temperature(0.0);
+
+  // Kept cold, the current state is always the best one, so any step that
+  // doesn't improve on it reheats
+  annealing.set_reheat(5.0, 1);
+  let mut reheats = 0;
+  for _ in 0..30 {
+    let step = annealing.step();
+    assert_eq!(step.reheated, !step.accepted);
+    if step.reheated {
+      assert_eq!(step.temperature, 5.0);
+      reheats += 1;
+      annealing.set_temperature(0.0);
+    }
+  }
+  assert!(reheats > 0);
+  assert_eq!(annealing.reheats(), reheats);
+
+  // Hot enough to keep almost anything, the current state drifts from the best
+  annealing.set_reheat(0.0, 0);
+  annealing.set_exponential_cooling(1.0);
+  annealing.set_temperature(1e9);
+  for _ in 0..100 {
+    if annealing.fitness() > annealing.best_fitness() {
+      break;
+    }
+    annealing.step();
+  }
+  assert!(annealing.fitness() > annealing.best_fitness());
+
+  let best_fitness = annealing.best_fitness();
+  annealing.restore_best();
+  assert_eq!(annealing.fitness(), best_fitness);
+  assert_eq!(annealing.image().genome_json(), annealing.best_genome_json());
+  let mut full = annealing.image().clone();
+  full.render();
+  for y in 0..60 {
+    for x in 0..60 {
+      assert_eq!(annealing.image().get_pixel(x, y), full.get_pixel(x, y));
+    }
+  }
+
+  // A best scored by another metric isn't comparable, so switching starts the
+  // run over from the current state
+  for _ in 0..100 {
+    if annealing.fitness() > annealing.best_fitness() {
+      break;
+    }
+    annealing.step();
+  }
+  assert!(annealing.fitness() > annealing.best_fitness());
+  annealing.set_fitness_metric(FitnessMetricKind::DeltaE76, false);
+  assert_eq!(annealing.best_fitness(), annealing.fitness());
+  assert_eq!(annealing.best_genome_json(), annealing.image().genome_json());
+}
+
+#[wasm_bindgen_test]
+fn test_step_size_adaptation() {
+  let reference = gradient_reference(20, 20);
+  let config = EvolutionConfig { adaptation_interval: 10, ..EvolutionConfig::default() };
+
+  // The climber sizes its steps with the 1/5th rule only; its genes keep
+  // their own step sizes at 1 even with self-adaptation on
+  for &one_fifth_rule in [true, false].iter() {
+    let config = EvolutionConfig { one_fifth_rule, ..config };
+    let mut climber = HillClimber::with_config(60, 60, reference.clone(), 20, 20, config).unwrap();
+    climber.reseed(10);
+    climber.run(100);
+    assert_eq!(climber.step_scale() != 1.0, one_fifth_rule);
+    for gene in genes_of(&climber.genome_json()) {
+      assert_eq!(gene["step_scale"], 1.0);
+    }
+  }
+
+  // Population members' genes self-adapt only when asked to
+  for &self_adaptive in [true, false].iter() {
+    let config = EvolutionConfig { self_adaptive, mutation_rate: 1.0, ..config };
+    let mut pop = Population::with_config(60, 60, reference.clone(), 20, 20, config).unwrap();
+    pop.reseed(11);
+    for _ in 0..6 {
+      pop.add_member();
+    }
+    for _ in 0..5 {
+      pop.evolve();
+    }
+    assert_eq!(pop.mean_step_scale() != 1.0, self_adaptive);
+  }
+}
+
+#[wasm_bindgen_test]
+fn test_boundary_modes() {
+  let cases = [
+    (BoundaryMode::Clamp, [(0.0, 0.0), (10.0, 10.0), (5.0, 5.0), (-3.0, 0.0), (13.0, 10.0), (-25.0, 0.0), (25.0, 10.0)]),
+    (BoundaryMode::Reflect, [(0.0, 0.0), (10.0, 10.0), (5.0, 5.0), (-3.0, 3.0), (13.0, 7.0), (-13.0, 7.0), (23.0, 3.0)]),
+    (BoundaryMode::Wrap, [(0.0, 0.0), (10.0, 10.0), (5.0, 5.0), (-3.0, 7.0), (13.0, 3.0), (-25.0, 5.0), (25.0, 5.0)]),
+  ];
+  for &(boundary, ref values) in cases.iter() {
+    for &(v, expected) in values.iter() {
+      assert_eq!(apply_boundary(v, 0.0, 10.0, boundary), expected, "{:?} of {}", boundary, v);
+    }
+  }
+
+  // Color channels are stepped over [-0.5, 255.5] and rounded, so wrapping goes
+  // from 255 straight to 0 and reflecting bounces off 255 like any other value
+  let channel = |v: f64, boundary: BoundaryMode| apply_boundary(v, -0.5, 255.5, boundary).round();
+  assert_eq!(channel(256.0, BoundaryMode::Wrap), 0.0);
+  assert_eq!(channel(-1.0, BoundaryMode::Wrap), 255.0);
+  assert_eq!(channel(256.0, BoundaryMode::Reflect), 255.0);
+  assert_eq!(channel(-1.0, BoundaryMode::Reflect), 0.0);
+  assert_eq!(channel(255.0, BoundaryMode::Wrap), 255.0);
+  assert_eq!(channel(0.0, BoundaryMode::Wrap), 0.0);
+}
+
+#[wasm_bindgen_test]
+fn test_random_step() {
+  let mut rng = Pcg32::seed_from_u64(15);
+  for &distribution in [MutationDistribution::Uniform, MutationDistribution::Gaussian, MutationDistribution::Cauchy].iter() {
+    for &jitter in [0.0, -1.0, f64::NAN].iter() {
+      assert_eq!(random_step(jitter, distribution, &mut rng), 0.0);
+    }
+  }
+
+  let samples = |distribution: MutationDistribution, rng: &mut Pcg32| -> Vec<f64> {
+    (0..4000).map(|_| random_step(2.0, distribution, rng)).collect()
+  };
+  let uniform = samples(MutationDistribution::Uniform, &mut rng);
+  assert!(uniform.iter().all(|s| s.abs() < 2.0));
+  assert!(uniform.iter().any(|&s| s > 1.9) && uniform.iter().any(|&s| s < -1.9));
+
+  // σ is half the jitter
+  let gaussian = samples(MutationDistribution::Gaussian, &mut rng);
+  let mean = gaussian.iter().sum::<f64>() / 4000.0;
+  let sd = (gaussian.iter().map(|s| (s - mean) * (s - mean)).sum::<f64>() / 4000.0).sqrt();
+  assert!(mean.abs() < 0.05);
+  assert!((sd - 1.0).abs() < 0.05);
+
+  // Half of a Cauchy's steps are within its scale, half the jitter, but some
+  // are far bigger than a Gaussian's
+  let mut cauchy: Vec<f64> = samples(MutationDistribution::Cauchy, &mut rng).iter().map(|s| s.abs()).collect();
+  cauchy.sort_by(|a, b| a.partial_cmp(b).unwrap());
+  assert!((cauchy[2000] - 1.0).abs() < 0.1);
+  assert!(cauchy[3999] > 10.0);
+}
+
+#[wasm_bindgen_test]
+fn test_compare_values_uses_each_pixels_own_channels() {
+  // An unrendered image is all white
+  let i = RandomImage::new(3, 1);
+  let mut values = vec![255; 12];
+  assert_eq!(i.compare_values(&values), 0.0);
+
+  // Only the third pixel's green channel differs
+  values[9] = 0;
+  assert_eq!(i.compare_values(&values), 255.0 * 255.0 / 12.0);
+}
+
+#[wasm_bindgen_test]
+fn test_ssim_metrics() {
+  let config = EvolutionConfig::default();
+  let mut pop = Population::with_config(60, 60, gradient_reference(30, 30), 30, 30, config).unwrap();
+  pop.reseed(5);
+  pop.add_member();
+
+  // SSIM error is 1 - SSIM, which stays within [0, 2]
+  pop.set_ssim_metric(7, 0.01, 0.03, 1, 0.0).unwrap();
+  let ssim = pop.best_fitness();
+  assert!(ssim > 0.0 && ssim <= 2.0);
+
+  // A full MSE weight leaves just MSE over RGB, scaled to [0, 1]
+  pop.set_fitness_metric(FitnessMetricKind::Mse, true);
+  let mse = pop.best_fitness();
+  pop.set_ssim_metric(7, 0.01, 0.03, 1, 1.0).unwrap();
+  assert!((pop.best_fitness() - mse / (255.0 * 255.0)).abs() < 1e-9);
+
+  let mut climber = HillClimber::with_config(64, 64, gradient_reference(32, 32), 32, 32, config).unwrap();
+  climber.reseed(5);
+  climber.set_metric(Box::new(MsSsim::default()));
+  let fitness = climber.fitness();
+  assert!(fitness > 0.0 && fitness <= 2.0);
+  climber.run(10);
+  assert!(climber.fitness() <= fitness);
+
+  assert!(Ssim::new(7, 0.01, 0.03, 0.5).is_ok());
+  assert!(MsSsim::new(7, 0.01, 0.03, 3, 0.5).is_ok());
+  assert_eq!(Ssim::new(0, 0.01, 0.03, 0.0).err(), Some(ConfigError::ZeroSsimWindow));
+  assert_eq!(MsSsim::new(7, 0.01, 0.03, 0, 0.0).err(), Some(ConfigError::ZeroSsimScales));
+  assert_eq!(Ssim::new(7, 0.01, 0.03, 1.5).err(), Some(ConfigError::MseWeight(1.5)));
+  assert_eq!(MsSsim::new(7, 0.01, 0.03, 3, -0.5).err(), Some(ConfigError::MseWeight(-0.5)));
+  for &(k1, k2) in [(f64::NAN, 0.03), (0.01, f64::INFINITY)].iter() {
+    match Ssim::new(7, k1, k2, 0.0) {
+      Err(ConfigError::SsimConstants(_, _)) => {}
+      _ => panic!("k1 = {} and k2 = {} should be rejected", k1, k2),
+    }
+  }
+}
+
+#[wasm_bindgen_test]
+fn test_ssim_error_of_noise() {
+  let reference = gradient_reference(30, 30);
+  let ssim = Ssim::default();
+  let noisy = |amplitude: i32| -> Vec<Pixel> {
+    let mut rng = Pcg32::seed_from_u64(16);
+    reference
+      .chunks(4)
+      .map(|v| {
+        let mut channel = |c: u8| (c as i32 + rng.gen_range(-amplitude, amplitude + 1)).clamp(0, 255) as u8;
+        Pixel::new(channel(v[0]), channel(v[1]), channel(v[2]), v[3])
+      })
+      .collect()
+  };
+
+  assert!(ssim.error(&noisy(0), &reference, 30, 30).abs() < 1e-9);
+  let errors: Vec<f64> = [0, 5, 20, 60, 120].iter().map(|&a| ssim.error(&noisy(a), &reference, 30, 30)).collect();
+  assert!(errors.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", errors);
+
+  // Nothing to compare, nothing wrong
+  assert_eq!(ssim.error(&[], &[], 0, 0), 0.0);
+  assert_eq!(ssim.error(&[], &[], 0, 5), 0.0);
+}
+
+#[wasm_bindgen_test]
+fn test_delta_e() {
+  let white = Lab::from_srgb(255, 255, 255);
+  assert!((white.l - 100.0).abs() < 0.01 && white.a.abs() < 0.01 && white.b.abs() < 0.01);
+  assert!((Lab::from_srgb(0, 0, 0).l).abs() < 1e-9);
+
+  // Test pairs from Sharma, Wu & Dalal's CIEDE2000 implementation notes
+  let pairs = [
+    ((50.0, 2.6772, -79.7751), (50.0, 0.0, -82.7485), 2.0425),
+    ((50.0, 2.5, 0.0), (50.0, 0.0, -2.5), 4.3065),
+    ((50.0, 2.5, 0.0), (73.0, 25.0, -18.0), 27.1492),
+    ((2.0776, 0.0795, -1.1350), (0.9033, -0.0636, -0.5514), 0.9082),
+  ];
+  for &((l1, a1, b1), (l2, a2, b2), expected) in pairs.iter() {
+    let (x, y) = (Lab { l: l1, a: a1, b: b1 }, Lab { l: l2, a: a2, b: b2 });
+    assert!((x.delta_e2000(&y) - expected).abs() < 1e-4);
+    assert!((y.delta_e2000(&x) - expected).abs() < 1e-4);
+  }
+
+  let config = EvolutionConfig::default();
+  let mut pop = Population::with_config(60, 60, gradient_reference(20, 20), 20, 20, config).unwrap();
+  pop.reseed(2);
+  pop.add_member();
+  pop.set_fitness_metric(FitnessMetricKind::DeltaE76, true);
+  let e76 = pop.best_fitness();
+  pop.set_fitness_metric(FitnessMetricKind::DeltaE2000, true);
+  let e2000 = pop.best_fitness();
+  assert!(e76 > 0.0 && e2000 > 0.0);
+  assert_ne!(e76, e2000);
+}
+
+#[wasm_bindgen_test]
+fn test_weight_maps() {
+  let config = EvolutionConfig::default();
+  let mut pop = Population::with_config(60, 60, gradient_reference(20, 20), 20, 20, config).unwrap();
+  pop.reseed(4);
+  pop.add_member();
+  let unweighted = pop.best_fitness();
+
+  // Uniform weights change nothing, whatever their scale
+  pop.set_weights(Some(vec![3.0; 400])).unwrap();
+  assert!((pop.best_fitness() - unweighted).abs() < 1e-9);
+
+  // Weighting only the left half scores only the left half
+  let left: Vec<f64> = (0..400).map(|i| if i % 20 < 10 { 1.0 } else { 0.0 }).collect();
+  pop.set_weights(Some(left)).unwrap();
+  assert_ne!(pop.best_fitness(), unweighted);
+
+  assert!(pop.set_weights(Some(vec![1.0; 399])).is_err());
+  assert!(pop.set_weights(Some(vec![0.0; 400])).is_err());
+  assert!(pop.set_weights(Some(vec![-1.0; 400])).is_err());
+
+  pop.set_ssim_metric(7, 0.01, 0.03, 1, 0.5).unwrap();
+  pop.use_edge_weight_map(0.1);
+  assert!(pop.best_fitness() > 0.0);
+
+  pop.clear_weight_map();
+  pop.set_fitness_metric(FitnessMetricKind::Mse, false);
+  assert!((pop.best_fitness() - unweighted).abs() < 1e-9);
+}
+
+#[wasm_bindgen_test]
+fn test_channel_weights() {
+  let config = EvolutionConfig::default();
+  let mut pop = Population::with_config(60, 60, gradient_reference(20, 20), 20, 20, config).unwrap();
+  pop.reseed(4);
+  pop.add_member();
+  let unweighted = pop.best_fitness();
+  pop.set_metric(Box::new(WeightedMse::new([2.0, 2.0, 2.0, 2.0]).unwrap()));
+  assert!((pop.best_fitness() - unweighted).abs() < 1e-9);
+  pop.set_metric(Box::new(WeightedMse::new([1.0, 1.0, 1.0, 0.0]).unwrap()));
+  assert_ne!(pop.best_fitness(), unweighted);
+
+  let bad = [
+    [-1.0, 1.0, 1.0, 1.0],
+    [1.0, f64::NAN, 1.0, 1.0],
+    [1.0, 1.0, f64::INFINITY, 1.0],
+    [0.0, 0.0, 0.0, 0.0],
+  ];
+  for &weights in bad.iter() {
+    match WeightedMse::new(weights) {
+      Err(ConfigError::ChannelWeights(_)) => {}
+      _ => panic!("{:?} should be rejected", weights),
+    }
+  }
+}
+
+#[wasm_bindgen_test]
+fn test_pyramid_coarse_to_fine() {
+  let config = EvolutionConfig {
+    pyramid_patience: 1,
+    pyramid_min_improvement: 0.5,
+    ..EvolutionConfig::default()
+  };
+  let mut pop = Population::with_config(64, 64, gradient_reference(32, 32), 32, 32, config).unwrap();
+  pop.reseed(6);
+  for _ in 0..4 {
+    pop.add_member();
+  }
+  let genome = pop.best_genome_json();
+
+  // 32x32, 16x16, 8x8, 4x4
+  pop.use_pyramid(5, false);
+  assert_eq!(pop.pyramid_levels(), 4);
+  assert_eq!(pop.pyramid_level(), 3);
+  // Changing level re-scores but doesn't touch the genes
+  assert_eq!(pop.best_genome_json(), genome);
+
+  // The first generation sets the bar; nothing halves the error in one
+  // generation, so each one after promotes
+  for expected in [3, 2, 1, 0, 0].iter() {
+    pop.evolve();
+    assert_eq!(pop.pyramid_level(), *expected);
+  }
+
+  pop.use_pyramid(3, true);
+  assert_eq!(pop.pyramid_levels(), 3);
+  assert_eq!(pop.pyramid_level(), 0);
+  assert!(pop.best_fitness() > 0.0);
+
+  pop.use_pyramid(1, false);
+  assert_eq!(pop.pyramid_levels(), 1);
+
+  // Turning the pyramid on and off leaves the metric's cached copies of the
+  // reference in step with it, so incremental scoring still agrees with a
+  // full one. Full-width rows of this 64x16 reference are the size of its
+  // 16x4 level.
+  let mut pop = Population::new(64, 16, &gradient_reference(64, 16), 64, 16);
+  pop.reseed(7);
+  for _ in 0..10 {
+    pop.add_member();
+  }
+  pop.set_fitness_metric(FitnessMetricKind::DeltaE76, false);
+  pop.use_pyramid(5, false);
+  pop.use_pyramid(1, false);
+  for _ in 0..30 {
+    pop.evolve();
+  }
+  let incremental: Vec<f64> = pop.members().iter().map(|m| m.fitness()).collect();
+  pop.set_fitness_metric(FitnessMetricKind::DeltaE76, false);
+  let full: Vec<f64> = pop.members().iter().map(|m| m.fitness()).collect();
+  for (incremental, full) in incremental.iter().zip(full.iter()) {
+    assert!((incremental - full).abs() < 1e-9, "{} vs {}", incremental, full);
+  }
+}
+
+#[wasm_bindgen_test]
+fn test_error_maps() {
+  let reference = gradient_reference(20, 20);
+  let mut i = RandomImage::new_with_gene_count(60, 60, 3);
+  let fitness = i.calculate_fitness(&reference, 20, 20);
+
+  let errors = i.error_map(&reference, 20, 20);
+  assert_eq!(errors.len(), 400);
+  let mean = errors.iter().sum::<f64>() / errors.len() as f64;
+  assert!((mean - fitness).abs() < 1e-6);
+
+  // The worst pixel is drawn white, and pixels are opaque
+  let heatmap = i.error_heatmap(&reference, 20, 20);
+  assert_eq!(heatmap.len(), 1600);
+  let worst = errors.iter().cloned().fold(0.0, f64::max);
+  let worst_index = errors.iter().position(|&e| e == worst).unwrap();
+  assert_eq!(&heatmap[worst_index * 4..worst_index * 4 + 4], &[255, 255, 255, 255]);
+  assert!(heatmap.chunks(4).all(|p| p[3] == 255));
+
+  let gene_errors = i.gene_errors(&reference, 20, 20);
+  assert_eq!(gene_errors.len(), 3);
+  assert!(gene_errors.iter().all(|e| (0.0..=worst).contains(e)));
+
+  // An error map finer than the canvas is read pixel for pixel instead of
+  // dividing by a zero tile size
+  let mut small = RandomImage::new_with_gene_count(10, 10, 3);
+  small.render();
+  let coverage = small.gene_coverage_errors(&vec![1.0; 400], 20, 20);
+  assert_eq!(coverage.len(), 3);
+  assert!(coverage.iter().all(|&e| e == 0.0 || e == 1.0));
+
+  let config = EvolutionConfig::default();
+  let mut pop = Population::with_config(60, 60, reference, 20, 20, config).unwrap();
+  pop.reseed(9);
+  pop.add_member();
+  assert_eq!(pop.best_error_map().len(), 400);
+  assert_eq!(pop.best_error_heatmap().len(), 1600);
+  assert_eq!(pop.best_gene_errors().len(), config.gene_count as usize);
+}
+
+#[wasm_bindgen_test]
+fn test_error_guided_mutation() {
+  // All of the error is in the left half of the reference
+  let errors: Vec<f64> = (0..400).map(|i| if i % 20 < 10 { 1.0 } else { 0.0 }).collect();
+  let guide = |bias: f64| ErrorGuide::new(errors.clone(), 20, 20, bias);
+  let mut rng = Pcg32::seed_from_u64(10);
+
+  let left_points = |bias: f64, rng: &mut Pcg32| {
+    let guide = guide(bias);
+    (0..1000).filter(|_| guide.sample_point(60, 60, rng).x < 30).count()
+  };
+  assert_eq!(left_points(1.0, &mut rng), 1000);
+  assert!(left_points(0.0, &mut rng) < 600);
+
+  // Genes picked for mutation cover the high error
+  let image = RandomImage::new_with_rng(60, 60, &EvolutionConfig::default(), &mut rng);
+  let gene_errors = guide(1.0).gene_errors(&image);
+  let high_error_picks = |bias: f64, rng: &mut Pcg32| {
+    let guide = guide(bias);
+    (0..1000).filter(|_| gene_errors[guide.pick_gene(&gene_errors, rng)] > 0.5).count()
+  };
+  let unguided = high_error_picks(0.0, &mut rng);
+  assert!(high_error_picks(1.0, &mut rng) > unguided + 200);
+
+  // So do new genes
+  let spawn = EvolutionConfig {
+    add_gene_rate: 1.0,
+    vertex_jitter: 0.0,
+    color_jitter: 0.0,
+    ..unstructured_config()
+  };
+  let left_spawns = |bias: f64, rng: &mut Pcg32| {
+    let guide = guide(bias);
+    let before = genes_of(&image.genome_json());
+    (0..300)
+      .filter(|_| {
+        let mut mutated = image.clone();
+        mutated.mutate_guided(&spawn, Some(&guide), rng);
+        let genes = genes_of(&mutated.genome_json());
+        let spawned = genes.iter().find(|g| !before.contains(g)).unwrap();
+        let triangle = &spawned["geometry"]["Triangle"];
+        let x: f64 = ["v0", "v1", "v2"].iter().map(|v| triangle[v][0].as_f64().unwrap()).sum::<f64>() / 3.0;
+        x < 0.5
+      })
+      .count()
+  };
+  let unguided = left_spawns(0.0, &mut rng);
+  assert!(left_spawns(1.0, &mut rng) > unguided + 60);
+
+  // The climber still never regresses with guidance on
+  let config = EvolutionConfig { error_bias: 1.0, mutation_mode: MutationMode::Gene, ..EvolutionConfig::default() };
+  let mut climber = HillClimber::with_config(60, 60, gradient_reference(20, 20), 20, 20, config).unwrap();
+  climber.reseed(10);
+  let fitness = climber.fitness();
+  climber.run(20);
+  assert!(climber.fitness() <= fitness);
+
+  assert!(!EvolutionConfig { error_bias: 1.5, ..EvolutionConfig::default() }.is_valid());
+}
+
+#[wasm_bindgen_test]
+fn test_init_strategies() {
+  let first_member_fitness = |init_strategy: InitStrategy, init_mean_background: bool| {
+    let config = EvolutionConfig {
+      init_strategy,
+      init_mean_background,
+      ..EvolutionConfig::default()
+    };
+    let mut pop = Population::with_config(60, 60, gradient_reference(20, 20), 20, 20, config).unwrap();
+    pop.reseed(11);
+    pop.add_member();
+    let genes = pop.best_gene_errors().len() as u32;
+    assert!(genes >= config.min_genes && genes <= config.max_genes);
+    pop.best_fitness()
+  };
+
+  let random = first_member_fitness(InitStrategy::Random, false);
+  assert!(first_member_fitness(InitStrategy::Random, true) < random);
+  assert!(first_member_fitness(InitStrategy::CentroidColor, true) < random);
+  assert!(first_member_fitness(InitStrategy::Delaunay, false) < random);
+}
+
+#[wasm_bindgen_test]
+fn test_optimize_gene_color() {
+  let reference = gradient_reference(20, 20);
+  let mut rng = Pcg32::seed_from_u64(12);
+  // Strokes only partly cover the pixels along their edges
+  let kinds = [ShapeKind::Triangle, ShapeKind::QuadraticStroke, ShapeKind::CubicStroke, ShapeKind::Mixed];
+  for round in 0..20 {
+    let config = EvolutionConfig { gene_count: 10, shape_kind: kinds[round % kinds.len()], ..EvolutionConfig::default() };
+    let mut i = RandomImage::new_with_rng(60, 60, &config, &mut rng);
+    let mut fitness = i.calculate_fitness(&reference, 20, 20);
+    for index in 0..10 {
+      i.optimize_gene_color(index, &reference, 20, 20);
+      // The solve redraws only around the gene, which must match a full render
+      let mut full = i.clone();
+      let solved = full.calculate_fitness(&reference, 20, 20);
+      assert_eq!(i.genome_json(), full.genome_json());
+      for y in 0..60 {
+        for x in 0..60 {
+          assert_eq!(i.get_pixel(x, y), full.get_pixel(x, y));
+        }
+      }
+      // Only the renderer's rounding keeps it from being exactly optimal
+      assert!(solved <= fitness + 1e-6, "{} > {}", solved, fitness);
+      fitness = solved;
+    }
+  }
+
+  let config = EvolutionConfig {
+    color_solve_rate: 0.5,
+    ..EvolutionConfig::default()
+  };
+  let mut climber = HillClimber::with_config(60, 60, gradient_reference(20, 20), 20, 20, config).unwrap();
+  climber.reseed(12);
+  let fitness = climber.fitness();
+  climber.run(20);
+  assert!(climber.fitness() < fitness);
+}
+
+#[wasm_bindgen_test]
+fn test_background_modes() {
+  let dark_reference: Vec<u8> = (0..400).flat_map(|_| vec![10, 20, 30, 255]).collect();
+  let first_member = |background_mode: BackgroundMode| {
+    let config = EvolutionConfig {
+      background_mode,
+      background_color: 0x0a141e,
+      ..EvolutionConfig::default()
+    };
+    let mut pop = Population::with_config(60, 60, dark_reference.clone(), 20, 20, config).unwrap();
+    pop.reseed(5);
+    pop.add_member();
+    (pop.best_fitness(), pop.best_genome_json())
+  };
+
+  let dark_background = "\"background\":{\"r\":10,\"g\":20,\"b\":30,\"a\":255}";
+  let (white_fitness, white_genome) = first_member(BackgroundMode::Evolve);
+  assert!(white_genome.contains("\"background\":{\"r\":255,\"g\":255,\"b\":255,\"a\":255}"));
+  for &mode in [BackgroundMode::Fixed, BackgroundMode::Mean, BackgroundMode::Median].iter() {
+    let (fitness, genome) = first_member(mode);
+    assert!(genome.contains(dark_background));
+    assert!(fitness < white_fitness);
+  }
+
+  // An evolving background drifts toward the reference, as it does by default
+  let mut climber = HillClimber::with_config(60, 60, dark_reference.clone(), 20, 20, EvolutionConfig::default()).unwrap();
+  climber.reseed(6);
+  climber.run(200);
+  assert!(!climber.genome_json().contains("\"r\":255,\"g\":255,\"b\":255,\"a\":255}"));
+
+  // ...but a fixed one never moves
+  let config = EvolutionConfig {
+    background_mode: BackgroundMode::Fixed,
+    background_mutation_rate: 1.0,
+    ..EvolutionConfig::default()
+  };
+  let mut climber = HillClimber::with_config(60, 60, dark_reference, 20, 20, config).unwrap();
+  climber.reseed(6);
+  climber.run(50);
+  let genome: Value = serde_json::from_str(&climber.genome_json()).unwrap();
+  assert_eq!(genome["version"], 3);
+  assert_eq!(genome["background"], serde_json::json!({"r": 255, "g": 255, "b": 255, "a": 255}));
+
+  assert!(!EvolutionConfig { background_color: 0x1000000, ..EvolutionConfig::default() }.is_valid());
+}
+
+#[wasm_bindgen_test]
+fn test_incremental_render_matches_full_render() {
+  let reference = gradient_reference(20, 20);
+  let config = EvolutionConfig {
+    add_gene_rate: 0.2,
+    remove_gene_rate: 0.2,
+    swap_gene_rate: 0.1,
+    move_gene_rate: 0.1,
+    background_mutation_rate: 0.1,
+    color_solve_rate: 0.1,
+    ..EvolutionConfig::default()
+  };
+  for &kind in [FitnessMetricKind::Mse, FitnessMetricKind::DeltaE76, FitnessMetricKind::Ssim].iter() {
+    let mut climber = HillClimber::with_config(60, 60, reference.clone(), 20, 20, config).unwrap();
+    climber.reseed(13);
+    climber.set_fitness_metric(kind, false);
+    for _ in 0..40 {
+      climber.step();
+      assert_renders_fully(climber.image());
+      if kind == FitnessMetricKind::Mse {
+        assert_eq!(climber.image().clone().calculate_fitness(&reference, 20, 20), climber.fitness());
+      }
+    }
+  }
+}
+
+// `image`'s pixels are what a full render of its genes draws
+fn assert_renders_fully(image: &RandomImage) {
+  let mut full = image.clone();
+  full.render();
+  for y in 0..image.height() {
+    for x in 0..image.width() {
+      assert_eq!(image.get_pixel(x, y), full.get_pixel(x, y));
+    }
+  }
+}
+
+#[wasm_bindgen_test]
+fn test_incremental_render_in_annealer_and_population() {
+  let reference = gradient_reference(20, 20);
+  let config = EvolutionConfig {
+    add_gene_rate: 0.2,
+    remove_gene_rate: 0.2,
+    swap_gene_rate: 0.1,
+    move_gene_rate: 0.1,
+    background_mutation_rate: 0.1,
+    color_solve_rate: 0.1,
+    ..EvolutionConfig::default()
+  };
+
+  // Hot enough that plenty of worse steps are accepted and plenty rejected
+  let mut annealing = SimulatedAnnealing::with_config(60, 60, reference.clone(), 20, 20, config).unwrap();
+  annealing.reseed(13, 1e4);
+  annealing.set_linear_cooling(0.0);
+  for _ in 0..40 {
+    annealing.step();
+    assert_renders_fully(annealing.image());
+    assert_eq!(annealing.image().clone().calculate_fitness(&reference, 20, 20), annealing.fitness());
+  }
+  annealing.restore_best();
+  assert_renders_fully(annealing.image());
+  assert_eq!(annealing.image().clone().calculate_fitness(&reference, 20, 20), annealing.fitness());
+
+  let mut pop = Population::with_config(60, 60, reference.clone(), 20, 20, config).unwrap();
+  pop.reseed(13);
+  for _ in 0..6 {
+    pop.add_member();
+  }
+  for _ in 0..20 {
+    pop.evolve();
+    for member in pop.members() {
+      assert_renders_fully(member);
+      assert_eq!(member.clone().calculate_fitness(&reference, 20, 20), member.fitness());
+    }
+  }
+}
+
+// The pixels `shape` covers on a `width` x `height` canvas
+fn covered(shape: &dyn Shape, width: u32, height: u32) -> BTreeSet<(u32, u32)> {
+  let mut pixels = BTreeSet::new();
+  shape.rasterize(width, height, None, &mut |p| {
+    pixels.insert((p.x, p.y));
+  });
+  pixels
+}
+
+#[wasm_bindgen_test]
+fn test_shape_geometry() {
+  // A shape over the whole canvas covers every pixel, the last row and column included
+  let (width, height) = (40, 30);
+  let everywhere = Triangle::new(Point2::new(-0.1, -0.1), Point2::new(2.1, -0.1), Point2::new(-0.1, 2.1));
+  let pixels = covered(&everywhere, width, height);
+  assert_eq!(pixels.len(), (width * height) as usize);
+  assert!(pixels.contains(&(width - 1, height - 1)));
+  assert!(pixels.contains(&(width - 1, 0)));
+  assert!(pixels.contains(&(0, height - 1)));
+
+  // A circle covers about pi r^2 pixels; lengths are fractions of the longer side
+  let circle = Circle::new(Point2::new(0.5, 0.5), 0.2);
+  let area = std::f64::consts::PI * 20.0 * 20.0;
+  let count = covered(&circle, 100, 100).len() as f64;
+  assert!((count - area).abs() < area * 0.02, "{} pixels for an area of {}", count, area);
+
+  // Turning a rectangle by 90 degrees swaps its sides
+  let turned = RotatedRect::new(Point2::new(0.5, 0.5), 0.2, 0.1, std::f64::consts::PI / 2.0);
+  let upright = RotatedRect::new(Point2::new(0.5, 0.5), 0.1, 0.2, 0.0);
+  let pixels = covered(&turned, 100, 100);
+  assert_eq!(pixels.len(), 20 * 40);
+  assert_eq!(pixels, covered(&upright, 100, 100));
+
+  // Boxes hold the pixels they visit and nothing past them, so boxes that only
+  // touch don't overlap
+  let bbox = BBox::spanning(2, 3, 5, 7);
+  let inside: BTreeSet<(u32, u32)> = bbox.clone().map(|p| (p.x, p.y)).collect();
+  assert_eq!(inside.len(), 4 * 5);
+  for y in 0..10 {
+    for x in 0..10 {
+      assert_eq!(bbox.contains(&Point2::new(x, y)), inside.contains(&(x, y)));
+    }
+  }
+  assert!(bbox.intersects(&BBox::spanning(5, 7, 8, 8)));
+  assert!(!bbox.intersects(&BBox::spanning(6, 0, 8, 8)));
+  assert!(!bbox.intersects(&BBox::spanning(0, 8, 8, 9)));
+  let union: BTreeSet<(u32, u32)> = bbox.union(&BBox::spanning(6, 8, 6, 8)).map(|p| (p.x, p.y)).collect();
+  assert_eq!(union.len(), 5 * 6);
+}
+
+#[wasm_bindgen_test]
+fn test_shape_kinds() {
+  let reference = gradient_reference(20, 20);
+  let kinds = [
+    (ShapeKind::Triangle, "Triangle"),
+    (ShapeKind::Circle, "Circle"),
+    (ShapeKind::Ellipse, "Ellipse"),
+    (ShapeKind::RotatedEllipse, "RotatedEllipse"),
+    (ShapeKind::RotatedRect, "RotatedRect"),
+  ];
+  for &(shape_kind, name) in kinds.iter() {
+    let config = EvolutionConfig {
+      shape_kind,
+      gene_count: 10,
+      mutation_mode: MutationMode::Component,
+      ..EvolutionConfig::default()
+    };
+    let mut climber = HillClimber::with_config(60, 60, reference.clone(), 20, 20, config).unwrap();
+    climber.reseed(14);
+    let genome = climber.genome_json();
+    assert_eq!(genome.matches(&format!("{{\"{}\":", name)).count(), 10);
+
+    let fitness = climber.fitness();
+    climber.run(30);
+    assert!(climber.fitness() < fitness);
+  }
+
+  // A mixed genome holds several kinds, and still renders incrementally
+  let config = EvolutionConfig {
+    shape_kind: ShapeKind::Mixed,
+    add_gene_rate: 0.2,
+    remove_gene_rate: 0.2,
+    ..EvolutionConfig::default()
+  };
+  let mut climber = HillClimber::with_config(60, 60, reference.clone(), 20, 20, config).unwrap();
+  climber.reseed(15);
+  let genome = climber.genome_json();
+  let present = kinds.iter().filter(|(_, name)| genome.contains(&format!("{{\"{}\":", name))).count();
+  assert!(present > 1);
+  for _ in 0..30 {
+    climber.step();
+    let mut full = climber.image().clone();
+    full.render();
+    for y in 0..60 {
+      for x in 0..60 {
+        assert_eq!(climber.image().get_pixel(x, y), full.get_pixel(x, y));
+      }
+    }
+  }
+}
+
+#[wasm_bindgen_test]
+fn test_polygon_genes() {
+  fn vertex_counts(genome: &str) -> Vec<usize> {
+    let genome: Value = serde_json::from_str(genome).unwrap();
+    genome["genes"]
+      .as_array()
+      .unwrap()
+      .iter()
+      .map(|gene| gene["geometry"]["Polygon"]["vertices"].as_array().unwrap().len())
+      .collect()
+  }
+
+  let reference = gradient_reference(20, 20);
+  for &fill_rule in [FillRule::EvenOdd, FillRule::NonZero].iter() {
+    let config = EvolutionConfig {
+      shape_kind: ShapeKind::Polygon,
+      polygon_fill_rule: fill_rule,
+      max_polygon_vertices: 6,
+      add_vertex_rate: 0.3,
+      remove_vertex_rate: 0.3,
+      gene_count: 10,
+      ..EvolutionConfig::default()
+    };
+    let mut climber = HillClimber::with_config(60, 60, reference.clone(), 20, 20, config).unwrap();
+    climber.reseed(16);
+    let counts = vertex_counts(&climber.genome_json());
+    assert_eq!(counts.len(), 10);
+    assert!(counts.iter().all(|n| (3..=6).contains(n)));
+
+    let fitness = climber.fitness();
+    let mut seen = vec![];
+    for _ in 0..40 {
+      climber.step();
+      let mut full = climber.image().clone();
+      full.render();
+      for y in 0..60 {
+        for x in 0..60 {
+          assert_eq!(climber.image().get_pixel(x, y), full.get_pixel(x, y));
+        }
+      }
+      seen.extend(vertex_counts(&climber.genome_json()));
+    }
+    assert!(climber.fitness() < fitness);
+    assert!(seen.iter().all(|n| (3..=6).contains(n)));
+  }
+
+  // Without jitter, a new vertex lands on an edge and leaves the outline as it
+  // was; vertices are added up to the most allowed and removed down to 3
+  let config = EvolutionConfig {
+    vertex_jitter: 0.0,
+    max_polygon_vertices: 6,
+    ..EvolutionConfig::default()
+  };
+  let mut rng = Pcg32::seed_from_u64(16);
+  let corners = vec![Point2::new(0.2, 0.2), Point2::new(0.8, 0.2), Point2::new(0.8, 0.8), Point2::new(0.2, 0.8)];
+  let mut polygon = Polygon::new(corners, FillRule::EvenOdd);
+  let square = covered(&polygon, 50, 50);
+  for n in 5..=6 {
+    assert!(polygon.insert_vertex(&config, &mut rng));
+    assert_eq!(polygon.parts(&EvolutionConfig::default()), n);
+    assert_eq!(covered(&polygon, 50, 50), square);
+  }
+  assert!(!polygon.insert_vertex(&config, &mut rng));
+  assert_eq!(polygon.parts(&EvolutionConfig::default()), 6);
+  for n in (3..=5).rev() {
+    assert!(polygon.remove_vertex(&mut rng));
+    assert_eq!(polygon.parts(&EvolutionConfig::default()), n);
+  }
+  assert!(!polygon.remove_vertex(&mut rng));
+  assert_eq!(polygon.parts(&EvolutionConfig::default()), 3);
+
+  let config = EvolutionConfig {
+    max_polygon_vertices: 2,
+    ..EvolutionConfig::default()
+  };
+  assert!(HillClimber::with_config(60, 60, reference, 20, 20, config).is_err());
+}
+
+#[wasm_bindgen_test]
+fn test_stroke_genes() {
+  let reference = gradient_reference(20, 20);
+  for &(shape_kind, controls) in [(ShapeKind::QuadraticStroke, 3), (ShapeKind::CubicStroke, 4)].iter() {
+    let config = EvolutionConfig {
+      shape_kind,
+      stroke_taper: true,
+      gene_count: 10,
+      ..EvolutionConfig::default()
+    };
+    let mut climber = HillClimber::with_config(60, 60, reference.clone(), 20, 20, config).unwrap();
+    climber.reseed(17);
+    let genome: Value = serde_json::from_str(&climber.genome_json()).unwrap();
+    let strokes: Vec<&Value> = genome["genes"].as_array().unwrap().iter().map(|gene| &gene["geometry"]["Stroke"]).collect();
+    assert_eq!(strokes.len(), 10);
+    for stroke in strokes.iter() {
+      assert_eq!(stroke["controls"].as_array().unwrap().len(), controls);
+      assert!(stroke["width"].as_f64().unwrap() > 0.0);
+      let taper = stroke["taper"].as_f64().unwrap();
+      assert!((0.0..=1.0).contains(&taper));
+    }
+
+    let fitness = climber.fitness();
+    for _ in 0..40 {
+      climber.step();
+      let mut full = climber.image().clone();
+      full.render();
+      for y in 0..60 {
+        for x in 0..60 {
+          assert_eq!(climber.image().get_pixel(x, y), full.get_pixel(x, y));
+        }
+      }
+    }
+    assert!(climber.fitness() < fitness);
+  }
+
+  // Untapered strokes keep their width
+  let config = EvolutionConfig {
+    shape_kind: ShapeKind::CubicStroke,
+    gene_count: 10,
+    ..EvolutionConfig::default()
+  };
+  let mut climber = HillClimber::with_config(60, 60, reference, 20, 20, config).unwrap();
+  climber.reseed(18);
+  climber.run(30);
+  let genome: Value = serde_json::from_str(&climber.genome_json()).unwrap();
+  for gene in genome["genes"].as_array().unwrap() {
+    assert_eq!(gene["geometry"]["Stroke"]["taper"].as_f64(), Some(1.0));
+  }
+
+  // ...and their shape mutations aren't spent on the taper
+  let config = EvolutionConfig {
+    shape_kind: ShapeKind::CubicStroke,
+    mutation_mode: MutationMode::Component,
+    channel_weight: 0.0,
+    alpha_weight: 0.0,
+    translate_weight: 0.0,
+    ..unstructured_config()
+  };
+  let mut rng = Pcg32::seed_from_u64(18);
+  let mut image = RandomImage::new_with_rng(60, 60, &config, &mut rng);
+  for _ in 0..100 {
+    let before = image.genome_json();
+    image.mutate_with_rng(&config, &mut rng);
+    assert_ne!(image.genome_json(), before);
+  }
+}
+
+#[wasm_bindgen_test]
+fn test_gradient_fills() {
+  let reference = gradient_reference(20, 20);
+  for &(gradient_kind, name) in [(GradientKind::Linear, "Linear"), (GradientKind::Radial, "Radial")].iter() {
+    for &mutation_mode in [MutationMode::All, MutationMode::Component].iter() {
+      let config = EvolutionConfig {
+        gradient_kind,
+        mutation_mode,
+        gene_count: 10,
+        ..EvolutionConfig::default()
+      };
+      let mut climber = HillClimber::with_config(60, 60, reference.clone(), 20, 20, config).unwrap();
+      climber.reseed(19);
+      let genome: Value = serde_json::from_str(&climber.genome_json()).unwrap();
+      for gene in genome["genes"].as_array().unwrap() {
+        assert!(gene["gradient"][name]["color"]["a"].is_u64());
+      }
+
+      let fitness = climber.fitness();
+      for _ in 0..40 {
+        climber.step();
+        let mut full = climber.image().clone();
+        full.render();
+        for y in 0..60 {
+          for x in 0..60 {
+            assert_eq!(climber.image().get_pixel(x, y), full.get_pixel(x, y));
+          }
+        }
+      }
+      assert!(climber.fitness() < fitness);
+    }
+  }
+
+  // A linear gradient draws each stop's color at that stop. With one faint
+  // gene over white, a stop's pixel is its color over white, up to how far the
+  // pixel's center is from the stop; only the red channel is visible here.
+  let config = EvolutionConfig {
+    gradient_kind: GradientKind::Linear,
+    gene_count: 1,
+    ..EvolutionConfig::default()
+  };
+  let red_over_white = |color: &Value| {
+    let a = color["a"].as_f64().unwrap() / 255.0;
+    color["r"].as_f64().unwrap() * a + 255.0 * (1.0 - a)
+  };
+  let point = |v: &Value| Point2::new(v[0].as_f64().unwrap() as f32, v[1].as_f64().unwrap() as f32);
+  let pixel_at = |v: &Value| {
+    let at = |c: f64| ((c * 60.0) as u32).min(59);
+    Point2::new(at(v[0].as_f64().unwrap()), at(v[1].as_f64().unwrap()))
+  };
+  let mut rng = Pcg32::seed_from_u64(21);
+  let mut checked = 0;
+  for _ in 0..1000 {
+    let mut image = RandomImage::new_with_rng(60, 60, &config, &mut rng);
+    image.render();
+    let genome: Value = serde_json::from_str(&image.genome_json()).unwrap();
+    let gene = &genome["genes"][0];
+    let (triangle, linear) = (&gene["geometry"]["Triangle"], &gene["gradient"]["Linear"]);
+    let triangle = Triangle::new(point(&triangle["v0"]), point(&triangle["v1"]), point(&triangle["v2"]));
+    let (start, end) = (pixel_at(&linear["start"]), pixel_at(&linear["end"]));
+    let (first, second) = (red_over_white(&gene["color"]), red_over_white(&linear["color"]));
+    let apart = (start.x as f64 - end.x as f64).hypot(start.y as f64 - end.y as f64);
+    if !triangle.contains(&start, 60, 60) || !triangle.contains(&end, 60, 60) || apart < 8.0 || (first - second).abs() < 8.0 {
+      continue;
+    }
+    let (at_start, at_end) = (image.get_pixel(start.x, start.y), image.get_pixel(end.x, end.y));
+    assert_ne!(at_start, at_end);
+    let red = |pixel: Pixel| pixel.r as f64;
+    assert!((red(at_start) - first).abs() < (red(at_start) - second).abs());
+    assert!((red(at_end) - second).abs() < (red(at_end) - first).abs());
+    checked += 1;
+  }
+  assert!(checked >= 10, "only {} gradients checked", checked);
+
+  // Flat genes write no gradient
+  let mut climber = HillClimber::with_config(60, 60, reference, 20, 20, EvolutionConfig::default()).unwrap();
+  climber.reseed(20);
+  let genome: Value = serde_json::from_str(&climber.genome_json()).unwrap();
+  for gene in genome["genes"].as_array().unwrap() {
+    assert!(gene["gradient"].is_null());
+  }
+}
+
+#[wasm_bindgen_test]
+fn test_resolution_independent_genes() {
+  let config = EvolutionConfig {
+    gene_count: 10,
+    ..EvolutionConfig::default()
+  };
+  let mut climber = HillClimber::with_config(30, 30, gradient_reference(10, 10), 10, 10, config).unwrap();
+  climber.reseed(23);
+  for _ in 0..20 {
+    climber.step();
+  }
+
+  // Vertices are fractions of the canvas, not whole pixels
+  let genome: Value = serde_json::from_str(&climber.genome_json()).unwrap();
+  let mut off_grid = 0;
+  for gene in genome["genes"].as_array().unwrap() {
+    let triangle = &gene["geometry"]["Triangle"];
+    for v in ["v0", "v1", "v2"].iter() {
+      for c in triangle[*v].as_array().unwrap() {
+        let c = c.as_f64().unwrap();
+        assert!((-0.1..=1.1).contains(&c));
+        if (c * 30.0).fract() != 0.0 {
+          off_grid += 1;
+        }
+      }
+    }
+  }
+  assert!(off_grid > 0);
+
+  // The same size draws the same image
+  let image = climber.image();
+  let same = climber.render_at(30, 30);
+  for y in 0..30 {
+    for x in 0..30 {
+      assert_eq!(same.get_pixel(x, y), image.get_pixel(x, y));
+    }
+  }
+
+  // A larger size draws the same picture: at 150 x 90, pixel (5x + 2, 3y + 1)
+  // has the same center as pixel (x, y) here, so they match everywhere,
+  // including along the edges and in the bottom-right corner
+  let large = climber.render_at(150, 90);
+  assert_eq!(large.size(), 150 * 90);
+  for y in 0..30 {
+    for x in 0..30 {
+      assert_eq!(large.get_pixel(x * 5 + 2, y * 3 + 1), image.get_pixel(x, y), "pixel ({}, {})", x, y);
+    }
+  }
+  assert_eq!(large.get_pixel(147, 88), image.get_pixel(29, 29));
+}
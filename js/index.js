@@ -61,6 +61,7 @@ function run(wasm) {
     }
     let best = pop.best_fitness();
     drawPixels(els.canvas2, pop.best_pixels(), width, height, wasm);
+    drawHeatmap(els.canvasShrink, pop.best_error_heatmap(), SHRUNK_DIMS);
     pop.evolve();
 
     // console.log(`err ${err} -> ${best.err}`);
@@ -86,6 +87,14 @@ function drawPixels(canvas, pixelsPtr, width, height, wasm) {
   ctx.putImageData(imageData, 0, 0);
 }
 
+// Draws RGBA bytes returned from WASM (e.g. an error heatmap) into the canvas
+function drawHeatmap(canvas, rgba, { width, height }) {
+  canvas.width = width;
+  canvas.height = height;
+  let imageData = new ImageData(new Uint8ClampedArray(rgba), width, height);
+  canvas.getContext('2d').putImageData(imageData, 0, 0);
+}
+
 // Find the slice of WASM memory that corresponds to the image's
 // pixels, and `putImage` those into the given canvas
 function drawImageFromWASMMemory(canvas, image, wasm) {